@@ -8,10 +8,12 @@ pub enum HybridType {
     Float,
     String,
     Bool,
+    Bytes,                            // raw binary data
     Void,
     Null,
     Array(Box<HybridType>),           // array[int]
     Map(Box<HybridType>, Box<HybridType>), // map{string, int}
+    Iter(Box<HybridType>),            // iter[int]: streamed item by item
 }
 
 /// A typed parameter: (name, type)
@@ -21,11 +23,19 @@ pub struct TypedParam {
     pub param_type: HybridType,
 }
 
+/// A block attribute such as `@timeout(5)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    pub name: String,
+    pub args: Vec<Expr>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Number(f64),
     Boolean(bool),
     StringLiteral(String),
+    BytesLiteral(Vec<u8>),             // b"..."
     Identifier(String),
     Assign {
         name: String,
@@ -33,6 +43,7 @@ pub enum Expr {
     },
     Array(Vec<Expr>),
     Map(Vec<(Expr, Expr)>),
+    Tuple(Vec<Expr>),                  // return a, b;
     Index {
         target: Box<Expr>,
         index: Box<Expr>,
@@ -59,6 +70,16 @@ pub enum Expr {
         condition: Box<Expr>,
         body: Box<Stmt>,
     },
+    /// `for item in iterable { ... }`; over a call to an `iter[T]` foreign
+    /// block the items are streamed rather than collected first
+    For {
+        variable: String,
+        iterable: Box<Expr>,
+        body: Box<Stmt>,
+    },
+    /// `a() |> f(x) |> g()`: each stage after the first is a call that gets
+    /// the previous stage's result as its first argument
+    Pipeline(Vec<Expr>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -90,6 +111,12 @@ pub enum Stmt {
         var_type: HybridType,
         value: Expr,
     },
+    DestructuringDeclaration {
+        is_const: bool,
+        names: Vec<String>,
+        var_types: Vec<HybridType>,
+        value: Expr,
+    },
     BlockDeclaration {
         name: String,
         parameters: Vec<TypedParam>,
@@ -98,9 +125,22 @@ pub enum Stmt {
         is_foreign: bool,               // true if preceded by #lang
         foreign_lang: Option<String>,   // e.g., "python", "rust"
         raw_body: Option<String>,       // raw code for foreign blocks
+        body_line: usize,               // 1-based source line of raw_body's first line
+        attributes: Vec<Attribute>,     // @name(args) lines before the block
+    },
+    /// `#lang setup { ... }`: code run once in the language's shared namespace.
+    /// `#python` calls that get a worker of their own (`@memory` / `@cpu`
+    /// blocks, `parallel` calls) run against a fresh replay of it instead.
+    SetupBlock {
+        lang: String,
+        raw_body: String,
+        body_line: usize,
     },
     Return(Option<Expr>),
     Block(Vec<Stmt>),
+    /// `parallel { ... }`: foreign block calls (each possibly bound with a
+    /// declaration or assignment) run concurrently
+    Parallel(Vec<Stmt>),
 }
 
 #[derive(Debug, Clone)]
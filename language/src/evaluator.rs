@@ -2,15 +2,19 @@
 // Evaluator for the Hybrid language
 
 use std::collections::HashMap;
-use crate::ast::{Expr, Stmt, BinaryOp, UnaryOp};
+use std::time::Duration;
+
+use crate::ast::{Attribute, Expr, Program, Stmt, BinaryOp, UnaryOp};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Number(f64),
     Boolean(bool),
     String(String),
+    Bytes(Vec<u8>),
     Array(Vec<Value>),
     Map(HashMap<String, Value>),
+    Tuple(Vec<Value>),
     Null,
 }
 
@@ -26,6 +30,21 @@ impl std::fmt::Display for Value {
             }
             Value::Boolean(b) => write!(f, "{}", b),
             Value::String(s) => write!(f, "\"{}\"", s), // Quote strings
+            Value::Bytes(bytes) => {
+                write!(f, "b\"")?;
+                for &byte in bytes {
+                    match byte {
+                        b'"' => write!(f, "\\\"")?,
+                        b'\\' => write!(f, "\\\\")?,
+                        b'\n' => write!(f, "\\n")?,
+                        b'\t' => write!(f, "\\t")?,
+                        b'\r' => write!(f, "\\r")?,
+                        0x20..=0x7e => write!(f, "{}", byte as char)?,
+                        _ => write!(f, "\\x{:02x}", byte)?,
+                    }
+                }
+                write!(f, "\"")
+            }
             Value::Array(arr) => {
                 write!(f, "[")?;
                 for (i, v) in arr.iter().enumerate() {
@@ -42,6 +61,14 @@ impl std::fmt::Display for Value {
                 }
                 write!(f, "}}")
             }
+            Value::Tuple(items) => {
+                write!(f, "(")?;
+                for (i, v) in items.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")?; }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, ")")
+            }
             Value::Null => write!(f, "null"),
         }
     }
@@ -53,27 +80,27 @@ pub struct Function {
     pub body: Vec<Stmt>,
 }
 
-/// A foreign function defined with #lang
-#[derive(Clone)]
-pub struct ForeignFunction {
-    pub parameters: Vec<String>,
-    pub raw_code: String,
-    pub language: String,
-}
-
 #[derive(Clone, Debug)]
 pub struct VariableInfo {
     pub value: Value,
     pub is_const: bool,
 }
 
-use crate::runtime::manager::{RuntimeManager, RuntimeValue};
+use crate::runtime::foreign::{Callbacks, ForeignRuntime};
+use crate::runtime::manager::{CrateDependency, ForeignBlock, RuntimeError, RuntimeManager, RuntimeValue};
+use crate::runtime::process::ResourceLimits;
+use crate::runtime::source_map::SourceLocation;
 
 pub struct Evaluator {
     variables: HashMap<String, VariableInfo>,
     functions: HashMap<String, Function>,
-    foreign_functions: HashMap<String, ForeignFunction>,
+    foreign_functions: HashMap<String, ForeignBlock>,
     runtime: RuntimeManager,
+    /// File name foreign block errors are reported against
+    source_name: String,
+    /// Set while a foreign block runs; the runtime manager is lent out to it
+    /// then, so native blocks it calls back into can't start another one
+    in_foreign_call: bool,
 }
 #[derive(Debug, Clone, PartialEq)]
 pub enum StatementResult {
@@ -85,6 +112,12 @@ pub enum StatementResult {
 pub enum EvalError {
     Message(String),
     Return(Value),
+    /// A foreign block failed; match on `kind` to tell timeouts, exceptions,
+    /// compile errors etc. apart
+    Runtime(RuntimeError),
+    /// Several calls in a `parallel { ... }` block failed (a single failure
+    /// is reported as `Runtime`)
+    Parallel(Vec<RuntimeError>),
 }
 
 impl From<String> for EvalError {
@@ -93,6 +126,12 @@ impl From<String> for EvalError {
     }
 }
 
+impl From<RuntimeError> for EvalError {
+    fn from(e: RuntimeError) -> Self {
+        EvalError::Runtime(e)
+    }
+}
+
 impl From<&str> for EvalError {
     fn from(s: &str) -> Self {
         EvalError::Message(s.to_string())
@@ -105,6 +144,14 @@ impl std::fmt::Display for EvalError {
         match self {
             EvalError::Message(msg) => write!(f, "{}", msg),
             EvalError::Return(val) => write!(f, "Uncaught return: {}", val),
+            EvalError::Runtime(e) => write!(f, "{}", e),
+            EvalError::Parallel(errors) => {
+                write!(f, "{} parallel calls failed:", errors.len())?;
+                for e in errors {
+                    write!(f, "\n{}", e)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -116,9 +163,26 @@ impl Evaluator {
             functions: HashMap::new(),
             foreign_functions: HashMap::new(),
             runtime: RuntimeManager::new(),
+            source_name: SourceLocation::default().file,
+            in_foreign_call: false,
         }
     }
     
+    /// Set the file name used in foreign block error locations
+    pub fn set_source_name(&mut self, name: impl Into<String>) {
+        self.source_name = name.into();
+    }
+    
+    /// Register a runtime for an additional `#lang` (or replace a built-in one)
+    pub fn register_runtime(&mut self, runtime: impl ForeignRuntime + 'static) {
+        self.runtime.register(runtime);
+    }
+    
+    /// Access the runtime manager, e.g. to configure default limits
+    pub fn runtime_mut(&mut self) -> &mut RuntimeManager {
+        &mut self.runtime
+    }
+    
     pub fn evaluate_statement(&mut self, stmt: &Stmt) -> Result<StatementResult, EvalError> {
         match stmt {
             Stmt::Expression(expr) => {
@@ -130,27 +194,42 @@ impl Evaluator {
                 self.variables.insert(name.clone(), VariableInfo { value: val, is_const: *is_const });
                 Ok(StatementResult::None)
             }
-            Stmt::BlockDeclaration { name, parameters, body, is_foreign, foreign_lang, raw_body, .. } => {
-                let param_names: Vec<String> = parameters.iter().map(|p| p.name.clone()).collect();
-                
+            Stmt::DestructuringDeclaration { is_const, names, value, .. } => {
+                let val = self.evaluate_expression(value)?;
+                self.destructure(names, *is_const, val)?;
+                Ok(StatementResult::None)
+            }
+            Stmt::BlockDeclaration { name, parameters, body, is_foreign, attributes, .. } => {
                 if *is_foreign {
                     // Store as foreign function
-                    let foreign_fn = ForeignFunction {
-                        parameters: param_names,
-                        raw_code: raw_body.clone().unwrap_or_default(),
-                        language: foreign_lang.clone().unwrap_or_default(),
-                    };
+                    let foreign_fn = self.foreign_block(stmt)?;
                     self.foreign_functions.insert(name.clone(), foreign_fn);
                 } else {
+                    if let Some(attr) = attributes.first() {
+                        return Err(EvalError::from(format!(
+                            "Attribute '@{}' is only supported on foreign blocks",
+                            attr.name
+                        )));
+                    }
+                    
                     // Store as native Hybrid function
                     let function = Function {
-                        parameters: param_names,
+                        parameters: parameters.iter().map(|p| p.name.clone()).collect(),
                         body: body.clone(),
                     };
                     self.functions.insert(name.clone(), function);
                 }
                 Ok(StatementResult::None)
             }
+            Stmt::Parallel(stmts) => {
+                self.evaluate_parallel(stmts)?;
+                Ok(StatementResult::None)
+            }
+            Stmt::SetupBlock { lang, raw_body, body_line } => {
+                let origin = SourceLocation::new(self.source_name.clone(), *body_line);
+                self.with_runtime(|runtime, callbacks| runtime.setup(lang, raw_body, &origin, callbacks))?;
+                Ok(StatementResult::None)
+            }
             Stmt::Return(expr) => {
                 if let Some(expr) = expr {
                     let value = self.evaluate_expression(expr)?;
@@ -179,21 +258,14 @@ impl Evaluator {
             Expr::Number(n) => Ok(Value::Number(*n)),
             Expr::Boolean(b) => Ok(Value::Boolean(*b)),
             Expr::StringLiteral(s) => Ok(Value::String(s.clone())),
+            Expr::BytesLiteral(b) => Ok(Value::Bytes(b.clone())),
             Expr::Identifier(name) => {
                 self.variables.get(name)
                     .map(|info| info.value.clone())
                     .ok_or_else(|| EvalError::from(format!("Undefined variable: {}", name)))
             }
             Expr::Assign { name, value } => {
-                if !self.variables.contains_key(name) {
-                    return Err(EvalError::from(format!("Undefined variable: {}", name)));
-                }
-                
-                let info = self.variables.get(name).unwrap();
-                if info.is_const {
-                    return Err(EvalError::from(format!("Cannot reassign constant '{}'", name)));
-                }
-                
+                self.check_assignable(name)?;
                 let val = self.evaluate_expression(value)?;
                 self.variables.insert(name.clone(), VariableInfo { value: val.clone(), is_const: false });
                 Ok(val)
@@ -209,20 +281,23 @@ impl Evaluator {
             }
             Expr::FunctionCall { name, arguments } => {
                 if name == "speak" {
-                    let mut output = String::new();
-                    for (i, arg) in arguments.iter().enumerate() {
-                        if i > 0 {
-                            output.push(' ');
-                        }
-                        let val = self.evaluate_expression(arg)?;
-                        output.push_str(&val.to_string());
+                    let mut values = Vec::new();
+                    for arg in arguments {
+                        values.push(self.evaluate_expression(arg)?);
                     }
-                    println!("{}", output);
+                    Self::speak(&values);
                     Ok(Value::Null)
+                } else if Self::is_conversion(name) {
+                    let mut values = Vec::new();
+                    for arg in arguments {
+                        values.push(self.evaluate_expression(arg)?);
+                    }
+                    Self::convert(name, values)
                 } else {
                     self.call_function(name, arguments)
                 }
             }
+            Expr::Pipeline(stages) => self.evaluate_pipeline(stages),
             Expr::If { condition, then_branch, else_branch } => {
                 let cond_val = self.evaluate_expression(condition)?;
                 if let Value::Boolean(b) = cond_val {
@@ -262,6 +337,7 @@ impl Evaluator {
                 }
                 Ok(last_val)
             }
+            Expr::For { variable, iterable, body } => self.evaluate_for(variable, iterable, body),
             Expr::Array(elements) => {
                 let mut values = Vec::new();
                 for expr in elements {
@@ -269,6 +345,13 @@ impl Evaluator {
                 }
                 Ok(Value::Array(values))
             }
+            Expr::Tuple(elements) => {
+                let mut values = Vec::new();
+                for expr in elements {
+                    values.push(self.evaluate_expression(expr)?);
+                }
+                Ok(Value::Tuple(values))
+            }
             Expr::Map(pairs) => {
                 let mut map = HashMap::new();
                 for (key_expr, val_expr) in pairs {
@@ -310,6 +393,15 @@ impl Evaluator {
                             _ => Err(EvalError::from("Map index must be a string")),
                         }
                     }
+                    Value::Bytes(bytes) => {
+                        match index_val {
+                            Value::Number(n) if n.fract() == 0.0 && n >= 0.0 && (n as usize) < bytes.len() => {
+                                Ok(Value::Number(bytes[n as usize] as f64))
+                            }
+                            Value::Number(n) => Err(EvalError::from(format!("Index {} out of bounds (len {})", n, bytes.len()))),
+                            _ => Err(EvalError::from("Bytes index must be a number")),
+                        }
+                    }
                     _ => Err(EvalError::from("Cannot index non-collection type")),
                 }
             }
@@ -346,6 +438,14 @@ impl Evaluator {
                     _ => Err(EvalError::from(format!("Cannot apply {:?} to strings", op))),
                 }
             }
+            (Value::Bytes(l), Value::Bytes(r)) => {
+                match op {
+                    BinaryOp::Add => Ok(Value::Bytes([l.as_slice(), r.as_slice()].concat())),
+                    BinaryOp::Equal => Ok(Value::Boolean(l == r)),
+                    BinaryOp::NotEqual => Ok(Value::Boolean(l != r)),
+                    _ => Err(EvalError::from(format!("Cannot apply {:?} to bytes", op))),
+                }
+            }
             (Value::Boolean(l), Value::Boolean(r)) => {
                 match op {
                     BinaryOp::Equal => Ok(Value::Boolean(l == r)),
@@ -381,22 +481,23 @@ impl Evaluator {
     fn call_function(&mut self, name: &str, arguments: &[Expr]) -> Result<Value, EvalError> {
         // Check for foreign function first
         if let Some(foreign_fn) = self.foreign_functions.get(name).cloned() {
+            if arguments.len() != foreign_fn.parameters.len() {
+                return Err(EvalError::from(format!(
+                    "Function {} expects {} arguments, got {}",
+                    name,
+                    foreign_fn.parameters.len(),
+                    arguments.len()
+                )));
+            }
+            
             let mut arg_values = Vec::new();
             for arg in arguments {
                 let value = self.evaluate_expression(arg)?;
                 arg_values.push(Self::value_to_runtime(&value));
             }
             
-            return match self.runtime.execute(&foreign_fn.language, name, &foreign_fn.raw_code, &foreign_fn.parameters, arg_values) {
-                Ok(results) => {
-                    if let Some(first) = results.into_iter().next() {
-                        Ok(Self::runtime_to_value(first))
-                    } else {
-                        Ok(Value::Null)
-                    }
-                }
-                Err(e) => Err(EvalError::from(format!("[{}] {}", e.language, e.message))),
-            };
+            let results = self.with_runtime(|runtime, callbacks| runtime.execute(&foreign_fn, arg_values, callbacks))?;
+            return Ok(Self::results_to_value(results));
         }
         
         if !self.functions.contains_key(name) {
@@ -420,7 +521,340 @@ impl Evaluator {
             arg_values.push(value);
         }
         
-        let function = self.functions[name].clone();
+        self.call_native(name, arg_values)
+    }
+    
+    /// Run `for variable in iterable { body }` over an array or tuple, or
+    /// over the items of an `iter[T]` foreign block as it produces them.
+    /// Between items the runtimes are free, so the body may call other
+    /// foreign blocks.
+    fn evaluate_for(&mut self, variable: &str, iterable: &Expr, body: &Stmt) -> Result<Value, EvalError> {
+        if let Some(info) = self.variables.get(variable)
+            && info.is_const
+        {
+            return Err(EvalError::from(format!("Cannot reassign constant '{}'", variable)));
+        }
+        
+        let streamed = match iterable {
+            Expr::FunctionCall { name, arguments } => self
+                .foreign_functions
+                .get(name)
+                .filter(|block| block.item_type().is_some())
+                .cloned()
+                .map(|block| (block, arguments)),
+            _ => None,
+        };
+        let Some((block, arguments)) = streamed else {
+            let items = match self.evaluate_expression(iterable)? {
+                Value::Array(items) | Value::Tuple(items) => items,
+                Value::Bytes(bytes) => bytes.into_iter().map(|byte| Value::Number(byte as f64)).collect(),
+                other => return Err(EvalError::from(format!("Cannot iterate over {}", other))),
+            };
+            let mut last_val = Value::Null;
+            for item in items {
+                last_val = self.run_loop_body(variable, item, body)?;
+            }
+            return Ok(last_val);
+        };
+        
+        if arguments.len() != block.parameters.len() {
+            return Err(EvalError::from(format!(
+                "Function {} expects {} arguments, got {}",
+                block.name,
+                block.parameters.len(),
+                arguments.len()
+            )));
+        }
+        let mut args = Vec::new();
+        for arg in arguments {
+            let value = self.evaluate_expression(arg)?;
+            args.push(RuntimeManager::value_to_json(&Self::value_to_runtime(&value)));
+        }
+        
+        let stream = self.with_runtime(|runtime, callbacks| runtime.open_stream(&block, &args, callbacks))?;
+        let mut last_val = Value::Null;
+        let outcome = loop {
+            let item = match self.with_runtime(|runtime, callbacks| runtime.next_item(&stream, callbacks)) {
+                Ok(Some(item)) => item,
+                Ok(None) => break Ok(last_val),
+                Err(e) => break Err(e),
+            };
+            let value = Self::runtime_to_value(RuntimeManager::json_to_value(item));
+            match self.run_loop_body(variable, value, body) {
+                Ok(v) => last_val = v,
+                Err(e) => break Err(e),
+            }
+        };
+        // Stops a block that still has items to give when the loop fails
+        self.runtime.close_stream(stream);
+        outcome
+    }
+    
+    /// Bind a loop variable and run the loop body, returning its value
+    fn run_loop_body(&mut self, variable: &str, item: Value, body: &Stmt) -> Result<Value, EvalError> {
+        self.variables.insert(variable.to_string(), VariableInfo { value: item, is_const: false });
+        match self.evaluate_statement(body)? {
+            StatementResult::Value(v) => Ok(v),
+            StatementResult::None => Ok(Value::Null),
+        }
+    }
+    
+    /// Check that `name` is a variable that may be assigned to
+    fn check_assignable(&self, name: &str) -> Result<(), EvalError> {
+        match self.variables.get(name) {
+            None => Err(EvalError::from(format!("Undefined variable: {}", name))),
+            Some(info) if info.is_const => Err(EvalError::from(format!("Cannot reassign constant '{}'", name))),
+            Some(_) => Ok(()),
+        }
+    }
+    
+    /// Bind the items of a tuple or array to `names`
+    fn destructure(&mut self, names: &[String], is_const: bool, value: Value) -> Result<(), EvalError> {
+        let items = match value {
+            Value::Tuple(items) | Value::Array(items) => items,
+            other => {
+                return Err(EvalError::from(format!(
+                    "Cannot destructure {} into {} names",
+                    other,
+                    names.len()
+                )))
+            }
+        };
+        
+        if items.len() != names.len() {
+            return Err(EvalError::from(format!(
+                "Cannot destructure {} values into {} names",
+                items.len(),
+                names.len()
+            )));
+        }
+        
+        for (name, val) in names.iter().zip(items) {
+            self.variables.insert(name.clone(), VariableInfo { value: val, is_const });
+        }
+        Ok(())
+    }
+    
+    /// Run the calls of a `parallel { ... }` block concurrently, then bind
+    /// their results in order. Arguments are evaluated up front, one call
+    /// after another; if any call fails nothing is bound and every failure
+    /// is reported.
+    fn evaluate_parallel(&mut self, stmts: &[Stmt]) -> Result<(), EvalError> {
+        let mut calls = Vec::new();
+        for stmt in stmts {
+            let call = match stmt {
+                Stmt::Expression(Expr::Assign { name, value }) => {
+                    self.check_assignable(name)?;
+                    value.as_ref()
+                }
+                Stmt::Expression(expr) => expr,
+                Stmt::VariableDeclaration { value, .. } | Stmt::DestructuringDeclaration { value, .. } => value,
+                _ => return Err(EvalError::from("Only block calls can run in a parallel block")),
+            };
+            let Expr::FunctionCall { name, arguments } = call else {
+                return Err(EvalError::from("Only block calls can run in a parallel block"));
+            };
+            let Some(block) = self.foreign_functions.get(name).cloned() else {
+                if self.functions.contains_key(name) {
+                    return Err(EvalError::from(format!(
+                        "'{}' is a native block; only foreign blocks can run in a parallel block",
+                        name
+                    )));
+                }
+                return Err(EvalError::from(format!("Undefined function: {}", name)));
+            };
+            if arguments.len() != block.parameters.len() {
+                return Err(EvalError::from(format!(
+                    "Function {} expects {} arguments, got {}",
+                    name,
+                    block.parameters.len(),
+                    arguments.len()
+                )));
+            }
+            
+            let mut args = Vec::new();
+            for arg in arguments {
+                let value = self.evaluate_expression(arg)?;
+                args.push(RuntimeManager::value_to_json(&Self::value_to_runtime(&value)));
+            }
+            calls.push((block, args));
+        }
+        
+        let results = self.with_runtime(|runtime, callbacks| Ok(runtime.execute_parallel(&calls, callbacks)))?;
+        let mut values = Vec::new();
+        let mut errors = Vec::new();
+        for (result, (block, _)) in results.into_iter().zip(&calls) {
+            match result.and_then(|json| RuntimeManager::split_results(json, block)) {
+                Ok(results) => values.push(Self::results_to_value(results)),
+                Err(e) => errors.push(e),
+            }
+        }
+        match errors.len() {
+            0 => {}
+            1 => return Err(EvalError::Runtime(errors.remove(0))),
+            _ => return Err(EvalError::Parallel(errors)),
+        }
+        
+        for (stmt, value) in stmts.iter().zip(values) {
+            match stmt {
+                Stmt::VariableDeclaration { is_const, name, .. } => {
+                    self.variables.insert(name.clone(), VariableInfo { value, is_const: *is_const });
+                }
+                Stmt::DestructuringDeclaration { is_const, names, .. } => self.destructure(names, *is_const, value)?,
+                Stmt::Expression(Expr::Assign { name, .. }) => {
+                    self.variables.insert(name.clone(), VariableInfo { value, is_const: false });
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+    
+    fn speak(values: &[Value]) {
+        let output: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        println!("{}", output.join(" "));
+    }
+    
+    /// `bytes(...)` and `string(...)`, which parse as calls to type names
+    fn is_conversion(name: &str) -> bool {
+        name == "bytes" || name == "string"
+    }
+    
+    /// `bytes(x)` encodes a string as UTF-8 or packs an array of ints
+    /// (0-255); `string(x)` decodes UTF-8 bytes or formats any other value
+    fn convert(name: &str, mut values: Vec<Value>) -> Result<Value, EvalError> {
+        if values.len() != 1 {
+            return Err(EvalError::from(format!("{}() expects 1 argument, got {}", name, values.len())));
+        }
+        match (name, values.remove(0)) {
+            ("bytes", Value::Bytes(bytes)) => Ok(Value::Bytes(bytes)),
+            ("bytes", Value::String(s)) => Ok(Value::Bytes(s.into_bytes())),
+            ("bytes", Value::Array(items)) => items
+                .iter()
+                .map(|item| match item {
+                    Value::Number(n) if n.fract() == 0.0 && (0.0..=255.0).contains(n) => Ok(*n as u8),
+                    other => Err(EvalError::from(format!("bytes() expects ints from 0 to 255, got {}", other))),
+                })
+                .collect::<Result<Vec<u8>, _>>()
+                .map(Value::Bytes),
+            ("string", Value::Bytes(bytes)) => String::from_utf8(bytes)
+                .map(Value::String)
+                .map_err(|e| EvalError::from(format!("string() expects UTF-8 bytes: {}", e))),
+            ("string", Value::String(s)) => Ok(Value::String(s)),
+            ("string", other) => Ok(Value::String(other.to_string())),
+            (_, other) => Err(EvalError::from(format!("Cannot convert {} to {}", other, name))),
+        }
+    }
+    
+    /// A foreign block's results as one value (a tuple for several)
+    fn results_to_value(results: Vec<RuntimeValue>) -> Value {
+        let mut values: Vec<Value> = results.into_iter().map(Self::runtime_to_value).collect();
+        match values.len() {
+            0 => Value::Null,
+            1 => values.remove(0),
+            _ => Value::Tuple(values),
+        }
+    }
+    
+    /// Evaluate `first |> f(...) |> g(...)`. Results of foreign stages stay
+    /// JSON while they flow into further foreign stages and only become a
+    /// Hybrid value when a native stage or the end of the pipeline needs one.
+    fn evaluate_pipeline(&mut self, stages: &[Expr]) -> Result<Value, EvalError> {
+        let (first, rest) = stages.split_first().ok_or_else(|| EvalError::from("Empty pipeline"))?;
+        let mut piped = match first {
+            Expr::FunctionCall { name, arguments } if self.foreign_functions.contains_key(name) => {
+                self.pipe_call(name, None, arguments)?
+            }
+            other => Piped::Value(self.evaluate_expression(other)?),
+        };
+        
+        for stage in rest {
+            let Expr::FunctionCall { name, arguments } = stage else {
+                return Err(EvalError::from("Pipeline stages after '|>' must be block calls"));
+            };
+            piped = self.pipe_call(name, Some(piped), arguments)?;
+        }
+        
+        piped.into_value()
+    }
+    
+    /// Call one pipeline stage with `input` (if any) as its first argument
+    fn pipe_call(&mut self, name: &str, input: Option<Piped>, arguments: &[Expr]) -> Result<Piped, EvalError> {
+        if let Some(block) = self.foreign_functions.get(name).cloned() {
+            let mut args = Vec::new();
+            if let Some(input) = input {
+                args.push(input.into_json());
+            }
+            for arg in arguments {
+                let value = self.evaluate_expression(arg)?;
+                args.push(RuntimeManager::value_to_json(&Self::value_to_runtime(&value)));
+            }
+            if args.len() != block.parameters.len() {
+                return Err(EvalError::from(format!(
+                    "Function {} expects {} arguments, got {}",
+                    name,
+                    block.parameters.len(),
+                    args.len()
+                )));
+            }
+            
+            let result = self.with_runtime(|runtime, callbacks| runtime.execute_json(&block, &args, callbacks))?;
+            return Ok(Piped::Json(result, Box::new(block)));
+        }
+        
+        let mut values = Vec::new();
+        if let Some(input) = input {
+            values.push(input.into_value()?);
+        }
+        for arg in arguments {
+            values.push(self.evaluate_expression(arg)?);
+        }
+        if name == "speak" {
+            Self::speak(&values);
+            return Ok(Piped::Value(Value::Null));
+        }
+        if Self::is_conversion(name) {
+            return Ok(Piped::Value(Self::convert(name, values)?));
+        }
+        Ok(Piped::Value(self.call_native(name, values)?))
+    }
+    
+    /// Run a foreign-runtime operation, lending it the runtime manager and
+    /// this evaluator as the `Callbacks` for native blocks it calls
+    fn with_runtime<T>(
+        &mut self,
+        operation: impl FnOnce(&mut RuntimeManager, &mut dyn Callbacks) -> Result<T, RuntimeError>,
+    ) -> Result<T, EvalError> {
+        if self.in_foreign_call {
+            return Err(EvalError::from(
+                "Foreign blocks cannot be called from within a callback (a native block called through hybrid.call)",
+            ));
+        }
+        
+        let mut runtime = std::mem::replace(&mut self.runtime, RuntimeManager::empty());
+        self.in_foreign_call = true;
+        let result = operation(&mut runtime, &mut NativeCallbacks { evaluator: self });
+        self.in_foreign_call = false;
+        self.runtime = runtime;
+        result.map_err(EvalError::from)
+    }
+    
+    /// Call a native block with already evaluated arguments
+    fn call_native(&mut self, name: &str, arg_values: Vec<Value>) -> Result<Value, EvalError> {
+        let function = self
+            .functions
+            .get(name)
+            .cloned()
+            .ok_or_else(|| EvalError::from(format!("Undefined function: {}", name)))?;
+        if arg_values.len() != function.parameters.len() {
+            return Err(EvalError::from(format!(
+                "Function {} expects {} arguments, got {}",
+                name,
+                function.parameters.len(),
+                arg_values.len()
+            )));
+        }
+        
         let saved_vars = self.variables.clone();
         
         for (param, value) in function.parameters.iter().zip(arg_values.iter()) {
@@ -449,7 +883,7 @@ impl Evaluator {
                             result = val;
                             break;
                         }
-                        EvalError::Message(_) => return Err(e), // Propagate errors
+                        EvalError::Message(_) | EvalError::Runtime(_) | EvalError::Parallel(_) => return Err(e), // Propagate errors
                     }
                 }
             }
@@ -459,6 +893,167 @@ impl Evaluator {
         Ok(result)
     }
     
+    /// The `ForeignBlock` a `#lang` block declaration describes
+    fn foreign_block(&mut self, stmt: &Stmt) -> Result<ForeignBlock, EvalError> {
+        let Stmt::BlockDeclaration { name, parameters, return_types, foreign_lang, raw_body, body_line, attributes, .. } = stmt else {
+            return Err(EvalError::from("Expected a foreign block declaration"));
+        };
+        let lang = foreign_lang.clone().unwrap_or_default();
+        let (limits, dependencies) = self.block_options(name, &lang, attributes)?;
+        Ok(ForeignBlock {
+            name: name.clone(),
+            language: lang,
+            code: raw_body.clone().unwrap_or_default(),
+            parameters: parameters.clone(),
+            return_types: return_types.clone(),
+            limits,
+            dependencies,
+            origin: SourceLocation::new(self.source_name.clone(), *body_line),
+        })
+    }
+    
+    /// Compile every foreign block declared in `program` (including those
+    /// nested in native blocks) before anything runs, returning all the
+    /// failures. Only literal attribute arguments are evaluated here, so an
+    /// argument like `@timeout(f())` never runs twice; blocks with other
+    /// arguments, or attributes that are invalid, are left for execution to
+    /// report.
+    pub fn validate(&mut self, program: &Program) -> Vec<EvalError> {
+        fn collect<'a>(stmt: &'a Stmt, found: &mut Vec<&'a Stmt>) {
+            match stmt {
+                Stmt::BlockDeclaration { is_foreign: true, .. } => found.push(stmt),
+                Stmt::BlockDeclaration { body, .. } | Stmt::Block(body) | Stmt::Parallel(body) => {
+                    body.iter().for_each(|stmt| collect(stmt, found))
+                }
+                Stmt::Expression(Expr::If { then_branch, else_branch, .. }) => {
+                    collect(then_branch, found);
+                    if let Some(else_branch) = else_branch {
+                        collect(else_branch, found);
+                    }
+                }
+                Stmt::Expression(Expr::While { body, .. } | Expr::For { body, .. }) => collect(body, found),
+                _ => {}
+            }
+        }
+        
+        fn literal_attributes(stmt: &Stmt) -> bool {
+            let Stmt::BlockDeclaration { attributes, .. } = stmt else {
+                return false;
+            };
+            attributes
+                .iter()
+                .flat_map(|attr| &attr.args)
+                .all(|arg| matches!(arg, Expr::Number(_) | Expr::StringLiteral(_) | Expr::Boolean(_)))
+        }
+        
+        let mut declarations = Vec::new();
+        program.statements.iter().for_each(|stmt| collect(stmt, &mut declarations));
+        let blocks: Vec<ForeignBlock> = declarations
+            .into_iter()
+            .filter(|stmt| literal_attributes(stmt))
+            .filter_map(|stmt| self.foreign_block(stmt).ok())
+            .collect();
+        self.runtime.validate(&blocks).into_iter().map(EvalError::Runtime).collect()
+    }
+    
+    /// Build a foreign block's limits from its `@timeout(secs)`,
+    /// `@memory(mb)` and `@cpu(secs)` attributes, and its crates from
+    /// `@crate("name", "version")` / `@crate("name", "version", "features")`
+    fn block_options(&mut self, block_name: &str, lang: &str, attributes: &[Attribute]) -> Result<(ResourceLimits, Vec<CrateDependency>), EvalError> {
+        let mut limits = ResourceLimits::default();
+        let mut dependencies = Vec::new();
+        
+        for attr in attributes {
+            if attr.name == "crate" {
+                if lang != "rust" {
+                    return Err(EvalError::from(format!(
+                        "Attribute '@crate' on '{}' is only supported on #rust blocks",
+                        block_name
+                    )));
+                }
+                dependencies.push(self.crate_dependency(block_name, attr)?);
+                continue;
+            }
+            
+            let amount = match attr.args.as_slice() {
+                [arg] => match self.evaluate_expression(arg)? {
+                    Value::Number(n) if n > 0.0 => n,
+                    other => {
+                        return Err(EvalError::from(format!(
+                            "Attribute '@{}' on '{}' expects a positive number, got {}",
+                            attr.name, block_name, other
+                        )))
+                    }
+                },
+                _ => {
+                    return Err(EvalError::from(format!(
+                        "Attribute '@{}' on '{}' expects exactly one argument",
+                        attr.name, block_name
+                    )))
+                }
+            };
+            
+            match attr.name.as_str() {
+                "timeout" => limits.timeout = Some(Duration::from_secs_f64(amount)),
+                "memory" => limits.memory_bytes = Some((amount * 1024.0 * 1024.0) as u64),
+                "cpu" => limits.cpu_seconds = Some(amount.ceil() as u64),
+                other => {
+                    return Err(EvalError::from(format!(
+                        "Unknown attribute '@{}' on block '{}'",
+                        other, block_name
+                    )))
+                }
+            }
+        }
+        
+        Ok((limits, dependencies))
+    }
+    
+    fn crate_dependency(&mut self, block_name: &str, attr: &Attribute) -> Result<CrateDependency, EvalError> {
+        let mut args = Vec::new();
+        for arg in &attr.args {
+            match self.evaluate_expression(arg)? {
+                Value::String(s) => args.push(s),
+                other => {
+                    return Err(EvalError::from(format!(
+                        "Attribute '@crate' on '{}' expects string arguments, got {}",
+                        block_name, other
+                    )))
+                }
+            }
+        }
+        
+        match args.as_slice() {
+            // The name is written into Cargo.toml as a bare key
+            [name, _] | [name, _, _]
+                if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') =>
+            {
+                Err(EvalError::from(format!(
+                    "Attribute '@crate' on '{}' has an invalid crate name {:?} (expected letters, digits, '_' or '-')",
+                    block_name, name
+                )))
+            }
+            [name, version] | [name, version, _] => Ok(CrateDependency {
+                name: name.clone(),
+                version: version.clone(),
+                features: args
+                    .get(2)
+                    .map(|features| {
+                        features
+                            .split(',')
+                            .map(|f| f.trim().to_string())
+                            .filter(|f| !f.is_empty())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            }),
+            _ => Err(EvalError::from(format!(
+                "Attribute '@crate' on '{}' expects (name, version) or (name, version, features)",
+                block_name
+            ))),
+        }
+    }
+    
     /// Convert Hybrid Value to RuntimeValue
     fn value_to_runtime(value: &Value) -> RuntimeValue {
         match value {
@@ -471,8 +1066,14 @@ impl Evaluator {
             }
             Value::Boolean(b) => RuntimeValue::Bool(*b),
             Value::String(s) => RuntimeValue::String(s.clone()),
+            Value::Bytes(b) => RuntimeValue::Bytes(b.clone()),
+            Value::Array(items) | Value::Tuple(items) => RuntimeValue::Array(items.iter().map(Self::value_to_runtime).collect()),
+            Value::Map(map) => RuntimeValue::Map(
+                map.iter()
+                    .map(|(k, v)| (k.clone(), Self::value_to_runtime(v)))
+                    .collect(),
+            ),
             Value::Null => RuntimeValue::Null,
-            _ => RuntimeValue::Null, // Arrays/Maps not yet supported
         }
     }
     
@@ -483,8 +1084,175 @@ impl Evaluator {
             RuntimeValue::Float(n) => Value::Number(n),
             RuntimeValue::Bool(b) => Value::Boolean(b),
             RuntimeValue::String(s) => Value::String(s),
+            RuntimeValue::Bytes(b) => Value::Bytes(b),
+            RuntimeValue::Array(items) => Value::Array(items.into_iter().map(Self::runtime_to_value).collect()),
+            RuntimeValue::Map(map) => Value::Map(
+                map.into_iter()
+                    .map(|(k, v)| (k, Self::runtime_to_value(v)))
+                    .collect(),
+            ),
             RuntimeValue::Null => Value::Null,
         }
     }
 }
 
+impl Default for Evaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A value flowing through a `|>` pipeline
+enum Piped {
+    Value(Value),
+    /// Raw result of the foreign block that produced it
+    Json(serde_json::Value, Box<ForeignBlock>),
+}
+
+impl Piped {
+    fn into_json(self) -> serde_json::Value {
+        match self {
+            Piped::Value(value) => RuntimeManager::value_to_json(&Evaluator::value_to_runtime(&value)),
+            Piped::Json(json, _) => json,
+        }
+    }
+    
+    fn into_value(self) -> Result<Value, EvalError> {
+        match self {
+            Piped::Value(value) => Ok(value),
+            Piped::Json(json, block) => {
+                let results = RuntimeManager::split_results(json, &block)?;
+                Ok(Evaluator::results_to_value(results))
+            }
+        }
+    }
+}
+
+/// Serves `hybrid.call` requests from foreign blocks by running native blocks
+struct NativeCallbacks<'a> {
+    evaluator: &'a mut Evaluator,
+}
+
+impl Callbacks for NativeCallbacks<'_> {
+    fn call(&mut self, name: &str, args: Vec<serde_json::Value>) -> Result<serde_json::Value, String> {
+        if self.evaluator.foreign_functions.contains_key(name) {
+            return Err(format!("'{}' is a foreign block; only native blocks can be called back", name));
+        }
+        
+        let args = args
+            .into_iter()
+            .map(|arg| Evaluator::runtime_to_value(RuntimeManager::json_to_value(arg)))
+            .collect();
+        let result = self.evaluator.call_native(name, args).map_err(|e| e.to_string())?;
+        Ok(RuntimeManager::value_to_json(&Evaluator::value_to_runtime(&result)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    /// Parse and run `source`, returning the evaluator for inspection
+    fn run(source: &str) -> Result<Evaluator, EvalError> {
+        let program = Parser::new(Lexer::new(source)).parse().expect("source should parse");
+        let mut evaluator = Evaluator::new();
+        for statement in &program.statements {
+            evaluator.evaluate_statement(statement)?;
+        }
+        Ok(evaluator)
+    }
+
+    fn variable(evaluator: &Evaluator, name: &str) -> Value {
+        evaluator.variables[name].value.clone()
+    }
+
+    const PAIR: &str = r#"(int, string) block pair(int n) {
+    return n * 2, "native";
+}
+"#;
+
+    #[test]
+    fn destructures_tuple_returns() {
+        let evaluator = run(&format!("{}(int, string) var (n, label) = pair(21);", PAIR)).unwrap();
+        assert_eq!(variable(&evaluator, "n"), Value::Number(42.0));
+        assert_eq!(variable(&evaluator, "label"), Value::String("native".to_string()));
+    }
+
+    #[test]
+    fn destructuring_arity_mismatch_is_an_error() {
+        let Err(error) = run(&format!("{}(int, string, int) var (n, label, extra) = pair(1);", PAIR)) else {
+            panic!("expected an arity error");
+        };
+        assert!(error.to_string().contains("Cannot destructure 2 values into 3 names"), "{}", error);
+    }
+
+    #[test]
+    fn callbacks_cannot_call_foreign_blocks() {
+        let source = r#"#python
+int block inner(int x) {
+    return x + 1
+}
+
+int block native(int x) {
+    return inner(x);
+}
+
+#python
+string block outer(int x) {
+    try:
+        return str(hybrid.call("native", x))
+    except hybrid.Error as e:
+        return str(e)
+}
+
+string var result = outer(1);
+"#;
+        let evaluator = run(source).unwrap();
+        let Value::String(result) = variable(&evaluator, "result") else {
+            panic!("expected a string result");
+        };
+        assert!(result.contains("Foreign blocks cannot be called from within a callback"), "{}", result);
+    }
+
+    #[test]
+    fn returning_out_of_a_for_loop_stops_the_stream() {
+        let source = r#"#python setup {
+    closed = False
+}
+
+#python
+iter[int] block naturals() {
+    global closed
+    n = 0
+    try:
+        while True:
+            n += 1
+            yield n
+    finally:
+        closed = True
+}
+
+#python
+bool block was_closed() {
+    return closed
+}
+
+int block first_over(int limit) {
+    for n in naturals() {
+        if (n > limit) {
+            return n;
+        }
+    }
+    return 0;
+}
+
+int var first = first_over(3);
+bool var closed = was_closed();
+"#;
+        let evaluator = run(source).unwrap();
+        assert_eq!(variable(&evaluator, "first"), Value::Number(4.0));
+        assert_eq!(variable(&evaluator, "closed"), Value::Boolean(true));
+    }
+}
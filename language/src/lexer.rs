@@ -7,6 +7,7 @@ pub enum Token {
     Number(f64),
     Boolean(bool),
     StringLiteral(String),
+    BytesLiteral(Vec<u8>),
     Identifier(String),
     
     // Operators
@@ -35,6 +36,8 @@ pub enum Token {
     Comma,
     Colon,        // :
     Semicolon,
+    At,           // @ (block attributes)
+    Pipe,         // |> (pipelines)
     
     // Keywords
     Var,
@@ -44,6 +47,9 @@ pub enum Token {
     If,
     Else,
     While,
+    For,
+    In,
+    Parallel,
     Speak,
     
     // Type keywords
@@ -51,10 +57,12 @@ pub enum Token {
     TypeFloat,
     TypeString,
     TypeBool,
+    TypeBytes,
     TypeVoid,
     TypeNull,
     TypeArray,
     TypeMap,
+    TypeIter,
     
     // Mutable blocks (foreign code)
     Mutable(String),  // #python, #rust, etc.
@@ -75,7 +83,7 @@ pub struct Lexer {
 impl Lexer {
     pub fn new(input: &str) -> Self {
         let chars: Vec<char> = input.chars().collect();
-        let current_char = chars.get(0).copied();
+        let current_char = chars.first().copied();
         
         Lexer {
             input: chars,
@@ -194,6 +202,57 @@ impl Lexer {
         string_value
     }
     
+    /// Read a `b"..."` literal: the same escapes as strings plus `\xNN`;
+    /// other characters are stored as their UTF-8 encoding
+    fn read_bytes(&mut self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.advance(); // Skip 'b'
+        self.advance(); // Skip opening quote
+        
+        while let Some(ch) = self.current_char {
+            if ch == '"' {
+                self.advance(); // Skip closing quote
+                break;
+            } else if ch == '\\' {
+                self.advance();
+                if let Some(escaped) = self.current_char {
+                    match escaped {
+                        'n' => bytes.push(b'\n'),
+                        't' => bytes.push(b'\t'),
+                        'r' => bytes.push(b'\r'),
+                        '0' => bytes.push(0),
+                        '\\' => bytes.push(b'\\'),
+                        '"' => bytes.push(b'"'),
+                        'x' => {
+                            let hex: String = [self.peek(), self.input.get(self.position + 2).copied()]
+                                .into_iter()
+                                .flatten()
+                                .collect();
+                            match u8::from_str_radix(&hex, 16) {
+                                Ok(byte) if hex.len() == 2 => {
+                                    bytes.push(byte);
+                                    self.advance();
+                                    self.advance();
+                                }
+                                _ => bytes.extend_from_slice(b"\\x"),
+                            }
+                        }
+                        _ => {
+                            bytes.push(b'\\');
+                            bytes.extend_from_slice(escaped.encode_utf8(&mut [0; 4]).as_bytes());
+                        }
+                    }
+                    self.advance();
+                }
+            } else {
+                bytes.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes());
+                self.advance();
+            }
+        }
+        
+        bytes
+    }
+    
     pub fn tokenize(&mut self) -> Vec<(Token, usize, usize)> {
         let mut tokens = Vec::new();
         
@@ -292,16 +351,27 @@ impl Lexer {
                     tokens.push((Token::Semicolon, start_line, start_col));
                     self.advance();
                 }
+                '@' => {
+                    tokens.push((Token::At, start_line, start_col));
+                    self.advance();
+                }
+                '|' if self.peek() == Some('>') => {
+                    tokens.push((Token::Pipe, start_line, start_col));
+                    self.advance();
+                    self.advance();
+                }
                 '#' => {
                     // Mutable block tag: #python, #rust, etc.
                     self.advance(); // consume '#'
-                    if let Some(c) = self.current_char {
-                        if c.is_alphabetic() {
-                            let lang = self.read_identifier();
-                            tokens.push((Token::Mutable(lang), start_line, start_col));
-                        }
+                    if self.current_char.is_some_and(|c| c.is_alphabetic()) {
+                        let lang = self.read_identifier();
+                        tokens.push((Token::Mutable(lang), start_line, start_col));
                     }
                 }
+                'b' if self.peek() == Some('"') => {
+                    let bytes = self.read_bytes();
+                    tokens.push((Token::BytesLiteral(bytes), start_line, start_col));
+                }
                 '"' => {
                     let string_value = self.read_string();
                     tokens.push((Token::StringLiteral(string_value), start_line, start_col));
@@ -320,6 +390,9 @@ impl Lexer {
                         "if" => Token::If,
                         "else" => Token::Else,
                         "while" => Token::While,
+                        "for" => Token::For,
+                        "in" => Token::In,
+                        "parallel" => Token::Parallel,
                         "speak" => Token::Speak,
                         "true" => Token::Boolean(true),
                         "false" => Token::Boolean(false),
@@ -328,10 +401,12 @@ impl Lexer {
                         "float" => Token::TypeFloat,
                         "string" => Token::TypeString,
                         "bool" => Token::TypeBool,
+                        "bytes" => Token::TypeBytes,
                         "void" => Token::TypeVoid,
                         "null" => Token::TypeNull,
                         "array" => Token::TypeArray,
                         "map" => Token::TypeMap,
+                        "iter" => Token::TypeIter,
                         _ => Token::Identifier(identifier),
                     };
                     tokens.push((token, start_line, start_col));
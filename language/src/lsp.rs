@@ -1,15 +1,21 @@
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
+use hybrid::ast::{HybridType, Stmt};
 use hybrid::lexer::Lexer;
 use hybrid::parser::Parser;
+use hybrid::runtime::manager::RuntimeManager;
 use std::collections::HashMap;
+use std::sync::Arc;
 use tokio::sync::Mutex;
 
-#[derive(Debug)]
 struct Backend {
     client: Client,
     documents: Mutex<HashMap<String, String>>,
+    /// Toolchain info for `#lang` diagnostics, detected the first time a
+    /// document uses each language. Detection runs the toolchain, so the
+    /// manager is only used from blocking tasks.
+    runtimes: Arc<std::sync::Mutex<RuntimeManager>>,
 }
 
 #[tower_lsp::async_trait]
@@ -57,8 +63,8 @@ impl LanguageServer for Backend {
 
     async fn completion(&self, _: CompletionParams) -> Result<Option<CompletionResponse>> {
         let keywords = vec![
-            "var", "const", "block", "return", "if", "else", "while", "speak",
-            "int", "float", "string", "bool", "void", "null", "array", "map",
+            "var", "const", "block", "return", "if", "else", "while", "for", "in", "parallel", "speak",
+            "int", "float", "string", "bool", "bytes", "void", "null", "array", "map", "iter",
             "true", "false",
         ];
 
@@ -82,8 +88,19 @@ impl Backend {
         
         let mut diagnostics = Vec::new();
         
-        if let Err(e) = parser.parse() {
-            diagnostics.push(Diagnostic {
+        match parser.parse() {
+            Ok(program) => {
+                let runtimes = Arc::clone(&self.runtimes);
+                let found = tokio::task::spawn_blocking(move || {
+                    let runtimes = runtimes.lock().unwrap_or_else(|e| e.into_inner());
+                    let mut found = Vec::new();
+                    runtime_diagnostics(&runtimes, &program.statements, &mut found);
+                    found
+                })
+                .await;
+                diagnostics.extend(found.unwrap_or_default());
+            }
+            Err(e) => diagnostics.push(Diagnostic {
                 range: Range {
                     start: Position {
                         line: e.line as u32,
@@ -97,7 +114,7 @@ impl Backend {
                 severity: Some(DiagnosticSeverity::ERROR),
                 message: e.message,
                 ..Default::default()
-            });
+            }),
         }
         
         self.client
@@ -110,6 +127,52 @@ impl Backend {
     }
 }
 
+/// Diagnostics for `#lang` blocks whose runtime is missing or lacks a
+/// feature they use
+fn runtime_diagnostics(runtimes: &RuntimeManager, statements: &[Stmt], diagnostics: &mut Vec<Diagnostic>) {
+    for stmt in statements {
+        let (lang, body_line, needs_streaming, is_setup) = match stmt {
+            Stmt::BlockDeclaration { foreign_lang: Some(lang), return_types, body_line, .. } => {
+                (lang, *body_line, matches!(return_types.as_slice(), [HybridType::Iter(_)]), false)
+            }
+            Stmt::SetupBlock { lang, body_line, .. } => (lang, *body_line, false, true),
+            Stmt::BlockDeclaration { body, .. } | Stmt::Block(body) => {
+                runtime_diagnostics(runtimes, body, diagnostics);
+                continue;
+            }
+            _ => continue,
+        };
+
+        let problem = match runtimes.info(lang) {
+            None => Some((DiagnosticSeverity::WARNING, format!("Unsupported runtime: #{}", lang))),
+            Some(info) if !info.is_available() => Some((
+                DiagnosticSeverity::WARNING,
+                format!("#{} needs '{}', which was not found (run 'hybrid doctor' for details)", lang, info.path.display()),
+            )),
+            Some(info) if is_setup && !info.capabilities.setup => {
+                Some((DiagnosticSeverity::ERROR, format!("#{} does not support setup blocks", lang)))
+            }
+            Some(info) if needs_streaming && !info.capabilities.streaming => {
+                Some((DiagnosticSeverity::ERROR, format!("#{} blocks cannot return iter[...]", lang)))
+            }
+            _ => None,
+        };
+        if let Some((severity, message)) = problem {
+            // Underline the block's first line of code (`body_line` is 1-based)
+            let line = body_line.saturating_sub(1) as u32;
+            diagnostics.push(Diagnostic {
+                range: Range {
+                    start: Position { line, character: 0 },
+                    end: Position { line: line + 1, character: 0 },
+                },
+                severity: Some(severity),
+                message,
+                ..Default::default()
+            });
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let stdin = tokio::io::stdin();
@@ -118,6 +181,7 @@ async fn main() {
     let (service, socket) = LspService::new(|client| Backend {
         client,
         documents: Mutex::new(HashMap::new()),
+        runtimes: Arc::new(std::sync::Mutex::new(RuntimeManager::new())),
     });
     Server::new(stdin, stdout, socket).serve(service).await;
 }
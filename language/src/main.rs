@@ -5,10 +5,15 @@
 use clap::{Parser as ClapParser, Subcommand};
 use std::fs;
 use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
 
 use hybrid::lexer::Lexer;
 use hybrid::parser::Parser;
 use hybrid::evaluator::{Evaluator, StatementResult};
+use hybrid::runtime::cache;
+use hybrid::runtime::manager::RuntimeManager;
+use hybrid::runtime::python::{PythonInterpreter, PythonRuntime};
 
 const VERSION: &str = "0.1.0";
 const BANNER: &str = r#"
@@ -43,6 +48,15 @@ enum Commands {
         /// The file to run
         #[arg(value_name = "FILE")]
         file: String,
+        /// Default timeout in seconds for each foreign block call
+        #[arg(long, value_name = "SECS")]
+        timeout: Option<f64>,
+        /// Python interpreter (or virtualenv directory) for #python blocks
+        #[arg(long, value_name = "PATH")]
+        python: Option<PathBuf>,
+        /// Skip compiling foreign blocks up front; errors then surface on first call
+        #[arg(long)]
+        no_validate: bool,
     },
     /// Start the interactive REPL
     Repl,
@@ -51,6 +65,9 @@ enum Commands {
         /// Attempt to fix common issues
         #[arg(long)]
         fix: bool,
+        /// Python interpreter (or virtualenv directory) to check instead of the configured one
+        #[arg(long, value_name = "PATH")]
+        python: Option<PathBuf>,
     },
     /// Initialize a new Hybrid project
     Init {
@@ -58,27 +75,49 @@ enum Commands {
         #[arg(value_name = "NAME")]
         name: Option<String>,
     },
+    /// Inspect or clear the compiled block cache
+    Cache {
+        #[command(subcommand)]
+        action: Option<CacheAction>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheAction {
+    /// List cached artifacts and their sizes (default)
+    List,
+    /// Remove every cached artifact
+    Clear,
 }
 
 fn main() {
     let cli = Cli::parse();
     
     match cli.command {
-        Some(Commands::Run { file }) => {
-            run_file(&file);
+        Some(Commands::Run { file, timeout, python, no_validate }) => {
+            if !run_file(&file, timeout, python, !no_validate) {
+                std::process::exit(1);
+            }
         }
         Some(Commands::Repl) => {
             run_repl();
         }
-        Some(Commands::Doctor { fix }) => {
-            run_doctor(fix);
+        Some(Commands::Doctor { fix, python }) => {
+            run_doctor(fix, python);
         }
         Some(Commands::Init { name }) => {
             run_init(name);
         }
+        Some(Commands::Cache { action }) => {
+            if !run_cache(action.unwrap_or(CacheAction::List)) {
+                std::process::exit(1);
+            }
+        }
         None => {
             if let Some(file) = cli.input_file {
-                run_file(&file);
+                if !run_file(&file, None, None, true) {
+                    std::process::exit(1);
+                }
             } else {
                 // Show welcome message and start REPL
                 print_welcome();
@@ -88,14 +127,30 @@ fn main() {
     }
 }
 
-fn run_file(filename: &str) {
-    match fs::read_to_string(filename) {
-        Ok(content) => {
-            if let Err(e) = execute_code(&content) {
-                eprintln!("Error: {}", e);
-            }
+/// Run a `.hyb` file, reporting any error; returns whether it ran to completion
+fn run_file(filename: &str, timeout: Option<f64>, python: Option<PathBuf>, validate: bool) -> bool {
+    let mut evaluator = Evaluator::new();
+    if let Some(path) = python {
+        evaluator.register_runtime(PythonRuntime::with_interpreter(PythonInterpreter::from_path(&path, "--python")));
+    }
+    if let Some(secs) = timeout.filter(|secs| *secs > 0.0) {
+        let runtime = evaluator.runtime_mut();
+        let mut limits = runtime.default_limits();
+        limits.timeout = Some(Duration::from_secs_f64(secs));
+        runtime.set_default_limits(limits);
+    }
+    
+    evaluator.set_source_name(filename);
+    let result = match fs::read_to_string(filename) {
+        Ok(content) => execute_code(&mut evaluator, &content, validate).map_err(|e| format!("Error: {}", e)),
+        Err(e) => Err(format!("Could not read file '{}': {}", filename, e)),
+    };
+    match result {
+        Ok(()) => true,
+        Err(message) => {
+            eprintln!("{}", message);
+            false
         }
-        Err(e) => eprintln!("Could not read file '{}': {}", filename, e),
     }
 }
 
@@ -105,34 +160,38 @@ fn print_welcome() {
     println!("A polyglot runtime for Python & Rust\n");
 }
 
-fn run_doctor(fix: bool) {
+fn run_doctor(fix: bool, python: Option<PathBuf>) {
     println!("🔬 Hybrid Doctor: Checking environment...\n");
     
     let mut issues = Vec::new();
     
-    // Check Rust
-    print!("Checking Rust... ");
-    io::stdout().flush().unwrap();
-    match std::process::Command::new("rustc").arg("--version").output() {
-        Ok(output) if output.status.success() => {
-            println!("✅ {}", String::from_utf8_lossy(&output.stdout).trim());
-        }
-        _ => {
-            println!("❌ Not found");
-            issues.push(("Rust", "Install Rust: curl --proto '=https' --tlsv1.2 -sSf https://sh.rustup.rs | sh"));
-        }
-    }
-    
-    // Check Python
-    print!("Checking Python... ");
-    io::stdout().flush().unwrap();
-    match std::process::Command::new("python3").arg("--version").output() {
-        Ok(output) if output.status.success() => {
-            println!("✅ {}", String::from_utf8_lossy(&output.stdout).trim());
+    // Check each runtime's toolchain (Python and Rust are required, the rest
+    // only for their blocks)
+    let mut manager = RuntimeManager::new();
+    let interpreter = match python {
+        Some(path) => PythonInterpreter::from_path(&path, "--python"),
+        None => PythonInterpreter::from_env(),
+    };
+    manager.register(PythonRuntime::with_interpreter(interpreter.clone()));
+    for language in manager.languages() {
+        print!("Checking #{}... ", language);
+        io::stdout().flush().unwrap();
+        let Some(info) = manager.info(&language) else { continue };
+        let mut origin = info.path.display().to_string();
+        if language == "python" {
+            origin = format!("{}, from {}", origin, interpreter.source);
         }
-        _ => {
-            println!("❌ Not found");
-            issues.push(("Python", "Install Python: https://www.python.org/downloads/"));
+        match (&info.version, install_hint(&language)) {
+            (Some(version), _) => {
+                println!("✅ {} ({}) [{}]", version, origin, info.capabilities.names().join(", "));
+            }
+            (None, Some(hint)) => {
+                println!("❌ Not found: {}", origin);
+                issues.push((language.clone(), hint));
+            }
+            (None, None) => {
+                println!("⚠️  '{}' not found (optional, needed for #{} blocks)", info.path.display(), language);
+            }
         }
     }
     
@@ -169,6 +228,15 @@ fn run_doctor(fix: bool) {
     }
 }
 
+/// How to install a required runtime's toolchain; `None` for optional ones
+fn install_hint(language: &str) -> Option<&'static str> {
+    match language {
+        "rust" => Some("Install Rust: curl --proto '=https' --tlsv1.2 -sSf https://sh.rustup.rs | sh"),
+        "python" => Some("Install Python: https://www.python.org/downloads/ (or point HYBRID_PYTHON / HYBRID_VENV at an interpreter)"),
+        _ => None,
+    }
+}
+
 fn run_init(name: Option<String>) {
     let project_name = name.unwrap_or_else(|| {
         std::env::current_dir()
@@ -206,8 +274,7 @@ string block py_greet(string name) {{
 // Rust mutable block example  
 #rust
 int block rs_double(int n) {{
-    let val: i64 = n.parse().unwrap();
-    val * 2
+    n * 2
 }}
 
 // Use the mutable blocks
@@ -253,6 +320,62 @@ Hybrid is a polyglot runtime that seamlessly integrates native code with Python
     println!("\n✅ Project initialized! Run: hybrid run src/main.hyb");
 }
 
+/// Run a `hybrid cache` subcommand; returns whether it succeeded
+fn run_cache(action: CacheAction) -> bool {
+    let root = cache::cache_root();
+    
+    match action {
+        CacheAction::List => {
+            let entries = match cache::entries() {
+                Ok(entries) => entries,
+                Err(e) => {
+                    eprintln!("Could not read cache at {}: {}", root.display(), e);
+                    return false;
+                }
+            };
+            
+            println!("📦 Hybrid cache: {}\n", root.display());
+            if entries.is_empty() {
+                println!("  (empty)");
+                return true;
+            }
+            
+            let mut total = 0;
+            for entry in &entries {
+                println!("  {:<8} {:<24} {:>10}", entry.language, entry.key, format_size(entry.size));
+                total += entry.size;
+            }
+            println!("\n  {} artifact(s), {} total", entries.len(), format_size(total));
+            true
+        }
+        CacheAction::Clear => match cache::clear() {
+            Ok(freed) => {
+                println!("🧹 Cleared {} ({} freed)", root.display(), format_size(freed));
+                true
+            }
+            Err(e) => {
+                eprintln!("Could not clear cache at {}: {}", root.display(), e);
+                false
+            }
+        },
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
 fn run_repl() {
     println!("Hybrid Language Interpreter v0.1.0");
     println!("Type 'help' for commands or 'exit' to quit");
@@ -308,13 +431,23 @@ fn print_repl_help() {
 
 
 
-fn execute_code(code: &str) -> Result<(), String> {
-    let mut evaluator = Evaluator::new();
+fn execute_code(evaluator: &mut Evaluator, code: &str, validate: bool) -> Result<(), String> {
     let lexer = Lexer::new(code);
     let mut parser = Parser::new(lexer);
     
     match parser.parse() {
         Ok(program) => {
+            if validate {
+                let errors = evaluator.validate(&program);
+                for e in &errors {
+                    eprintln!("Validation error: {}", e);
+                }
+                match errors.len() {
+                    0 => {}
+                    1 => return Err("1 foreign block failed validation".to_string()),
+                    n => return Err(format!("{} foreign blocks failed validation", n)),
+                }
+            }
             for statement in program.statements {
                 if let Err(e) = evaluator.evaluate_statement(&statement) {
                     return Err(format!("Runtime error: {}", e));
@@ -1,7 +1,7 @@
 // parser.rs
 // Recursive descent parser for the Hybrid language
 
-use crate::ast::{Expr, Stmt, Program, BinaryOp, UnaryOp, HybridType, TypedParam};
+use crate::ast::{Attribute, Expr, Stmt, Program, BinaryOp, UnaryOp, HybridType, TypedParam};
 use crate::lexer::{Token, Lexer};
 
 #[derive(Debug, Clone)]
@@ -81,9 +81,25 @@ impl Parser {
     }
 
     fn parse_statement(&mut self) -> Result<Stmt, ParseError> {
+        if matches!(self.current_token(), Token::At) {
+            return self.parse_attributed_statement();
+        }
+        
         // Check for mutable block: #python, #rust, etc.
         if let Token::Mutable(lang) = self.current_token().clone() {
             self.advance(); // consume #lang
+            
+            // `#lang setup { ... }` prelude shared by the language's blocks
+            if matches!(self.current_token(), Token::Identifier(word) if word == "setup") {
+                self.advance();
+                let brace_pos = self.current_pos();
+                if !self.match_token(&Token::LeftBrace) {
+                    return self.error("Expected '{' after 'setup'");
+                }
+                let (raw_body, body_line) = self.capture_raw_block_body(brace_pos)?;
+                return Ok(Stmt::SetupBlock { lang, raw_body, body_line });
+            }
+            
             // Next should be type then block
             if self.is_type_token() {
                 let var_type = self.parse_type()?;
@@ -113,14 +129,18 @@ impl Parser {
             }
         } else if matches!(self.current_token(), Token::LeftParen) {
             // Multi-return block: `(int, string) block name() {}`
-            let return_types = self.parse_return_type_tuple()?;
-            if !matches!(self.current_token(), Token::Block) {
-                return self.error("Expected 'block' after return type tuple");
+            // or destructuring: `(int, string) var (n, s) = f();`
+            let types = self.parse_return_type_tuple()?;
+            match self.current_token() {
+                Token::Block => self.parse_typed_block_declaration(types, None),
+                Token::Var => self.parse_destructuring_declaration(types, false),
+                Token::Const => self.parse_destructuring_declaration(types, true),
+                _ => self.error("Expected 'block', 'var', or 'const' after type tuple"),
             }
-            self.parse_typed_block_declaration(return_types, None)
         } else {
             match self.current_token() {
                 Token::Return => self.parse_return_statement(),
+                Token::Parallel => self.parse_parallel_statement(),
                 _ => {
                     let expr = self.parse_expression()?;
                     if self.match_token(&Token::Semicolon) {
@@ -132,12 +152,58 @@ impl Parser {
         }
     }
     
+    /// Parse `@name(args)` attributes and the block declaration they annotate
+    fn parse_attributed_statement(&mut self) -> Result<Stmt, ParseError> {
+        let mut attributes = Vec::new();
+        
+        while self.match_token(&Token::At) {
+            let name = match self.current_token().clone() {
+                Token::Identifier(name) => name,
+                _ => return self.error("Expected attribute name after '@'"),
+            };
+            self.advance();
+            
+            let mut args = Vec::new();
+            if self.match_token(&Token::LeftParen) {
+                while !matches!(self.current_token(), Token::RightParen) {
+                    args.push(self.parse_expression()?);
+                    if matches!(self.current_token(), Token::Comma) {
+                        self.advance();
+                    } else if !matches!(self.current_token(), Token::RightParen) {
+                        return self.error("Expected ',' or ')' in attribute arguments");
+                    }
+                }
+                self.advance(); // consume ')'
+            }
+            
+            attributes.push(Attribute { name, args });
+        }
+        
+        match self.parse_statement()? {
+            Stmt::BlockDeclaration { name, parameters, return_types, body, is_foreign, foreign_lang, raw_body, body_line, attributes: mut inner } => {
+                attributes.append(&mut inner);
+                Ok(Stmt::BlockDeclaration {
+                    name,
+                    parameters,
+                    return_types,
+                    body,
+                    is_foreign,
+                    foreign_lang,
+                    raw_body,
+                    body_line,
+                    attributes,
+                })
+            }
+            _ => self.error("Attributes can only be applied to block declarations"),
+        }
+    }
+    
     fn is_type_token(&self) -> bool {
         matches!(
             self.current_token(),
             Token::TypeInt | Token::TypeFloat | Token::TypeString | 
-            Token::TypeBool | Token::TypeVoid | Token::TypeNull |
-            Token::TypeArray | Token::TypeMap
+            Token::TypeBool | Token::TypeBytes | Token::TypeVoid | Token::TypeNull |
+            Token::TypeArray | Token::TypeMap | Token::TypeIter
         )
     }
     
@@ -147,6 +213,7 @@ impl Parser {
             Token::TypeFloat => { self.advance(); Ok(HybridType::Float) }
             Token::TypeString => { self.advance(); Ok(HybridType::String) }
             Token::TypeBool => { self.advance(); Ok(HybridType::Bool) }
+            Token::TypeBytes => { self.advance(); Ok(HybridType::Bytes) }
             Token::TypeVoid => { self.advance(); Ok(HybridType::Void) }
             Token::TypeNull => { self.advance(); Ok(HybridType::Null) }
             Token::TypeArray => {
@@ -160,6 +227,17 @@ impl Parser {
                 }
                 Ok(HybridType::Array(Box::new(inner)))
             }
+            Token::TypeIter => {
+                self.advance(); // consume 'iter'
+                if !self.match_token(&Token::LeftBracket) {
+                    return self.error("Expected '[' after 'iter'");
+                }
+                let inner = self.parse_type()?;
+                if !self.match_token(&Token::RightBracket) {
+                    return self.error("Expected ']' after iter item type");
+                }
+                Ok(HybridType::Iter(Box::new(inner)))
+            }
             Token::TypeMap => {
                 self.advance(); // consume 'map'
                 if !self.match_token(&Token::LeftBrace) {
@@ -222,6 +300,56 @@ impl Parser {
         }
     }
     
+    fn parse_destructuring_declaration(&mut self, var_types: Vec<HybridType>, is_const: bool) -> Result<Stmt, ParseError> {
+        self.advance(); // consume 'var' or 'const'
+        
+        if !self.match_token(&Token::LeftParen) {
+            return self.error("Expected '(' before destructured names");
+        }
+        
+        let mut names = Vec::new();
+        while !matches!(self.current_token(), Token::RightParen) {
+            if let Token::Identifier(name) = self.current_token().clone() {
+                names.push(name);
+                self.advance();
+            } else {
+                return self.error("Expected identifier in destructuring declaration");
+            }
+            
+            if matches!(self.current_token(), Token::Comma) {
+                self.advance();
+            } else if !matches!(self.current_token(), Token::RightParen) {
+                return self.error("Expected ',' or ')' in destructuring declaration");
+            }
+        }
+        self.advance(); // consume ')'
+        
+        if names.len() != var_types.len() {
+            return self.error(&format!(
+                "Destructuring declares {} types but {} names",
+                var_types.len(),
+                names.len()
+            ));
+        }
+        
+        if !self.match_token(&Token::Assign) {
+            return self.error("Expected '=' after destructured names");
+        }
+        
+        let value = self.parse_expression()?;
+        
+        if self.match_token(&Token::Semicolon) {
+            // Optional semicolon
+        }
+        
+        Ok(Stmt::DestructuringDeclaration {
+            is_const,
+            names,
+            var_types,
+            value,
+        })
+    }
+    
     fn parse_typed_block_declaration(&mut self, return_types: Vec<HybridType>, foreign_lang: Option<String>) -> Result<Stmt, ParseError> {
         self.advance(); // consume 'block'
         
@@ -261,13 +389,14 @@ impl Parser {
                 return self.error("Expected ')' after parameters");
             }
             
+            let brace_pos = self.current_pos();
             if !self.match_token(&Token::LeftBrace) {
                 return self.error("Expected '{' to start block body");
             }
             
             // For foreign blocks, capture raw body
             if foreign_lang.is_some() {
-                let raw = self.capture_raw_block_body(&name)?;
+                let (raw, body_line) = self.capture_raw_block_body(brace_pos)?;
                 return Ok(Stmt::BlockDeclaration {
                     name,
                     parameters,
@@ -276,6 +405,8 @@ impl Parser {
                     is_foreign: true,
                     foreign_lang,
                     raw_body: Some(raw),
+                    body_line,
+                    attributes: Vec::new(),
                 });
             }
             
@@ -298,6 +429,8 @@ impl Parser {
                 is_foreign: false,
                 foreign_lang: None,
                 raw_body: None,
+                body_line: 0,
+                attributes: Vec::new(),
             })
         } else {
             self.error("Expected identifier after 'block'")
@@ -305,59 +438,73 @@ impl Parser {
     }
     
     /// Capture raw text until matching closing brace (for foreign blocks)
-    /// Uses the source string to extract actual code content
-    fn capture_raw_block_body(&mut self, block_name: &str) -> Result<String, ParseError> {
-        // Find the block definition in source and extract the body
-        // Pattern: block_name(...) { ...body... }
-        let source = &self.source;
-        
-        // Find the block name in source
-        if let Some(name_pos) = source.find(block_name) {
-            // Find the opening brace after the block name
-            let after_name = &source[name_pos..];
-            if let Some(brace_offset) = after_name.find('{') {
-                let body_start = name_pos + brace_offset + 1;
-                let remaining = &source[body_start..];
-                
-                // Find matching closing brace
-                let mut brace_depth = 1;
-                let mut body_end = 0;
-                
-                for (i, ch) in remaining.chars().enumerate() {
-                    match ch {
-                        '{' => brace_depth += 1,
-                        '}' => {
-                            brace_depth -= 1;
-                            if brace_depth == 0 {
-                                body_end = i;
-                                break;
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-                
-                if brace_depth == 0 {
-                    let raw_body = remaining[..body_end].trim().to_string();
-                    
-                    // Advance parser past all tokens until we hit the matching }
-                    let mut depth = 1;
-                    while depth > 0 {
-                        match self.current_token() {
-                            Token::LeftBrace => depth += 1,
-                            Token::RightBrace => depth -= 1,
-                            Token::EOF => return self.error("Unexpected EOF in foreign block"),
-                            _ => {}
-                        }
-                        self.advance();
+    /// Uses the source string to extract actual code content, starting just
+    /// after the opening brace at `brace_pos` (line, column). Returns the body
+    /// and the 1-based source line its first line comes from.
+    fn capture_raw_block_body(&mut self, brace_pos: (usize, usize)) -> Result<(String, usize), ParseError> {
+        let body_start = match self.source_offset(brace_pos) {
+            Some(offset) => offset + 1,
+            None => return self.error("Could not extract foreign block body"),
+        };
+        let remaining = &self.source[body_start..];
+        
+        // Find matching closing brace
+        let mut brace_depth = 1;
+        let mut body_end = 0;
+        
+        for (i, ch) in remaining.char_indices() {
+            match ch {
+                '{' => brace_depth += 1,
+                '}' => {
+                    brace_depth -= 1;
+                    if brace_depth == 0 {
+                        body_end = i;
+                        break;
                     }
-                    
-                    return Ok(raw_body);
                 }
+                _ => {}
+            }
+        }
+        
+        if brace_depth != 0 {
+            return self.error("Could not extract foreign block body");
+        }
+        
+        // Drop surrounding blank lines but keep the first line's indentation,
+        // so indentation-sensitive bodies (Python) stay consistent
+        let body = remaining[..body_end].trim_end();
+        let first_line = body
+            .find(|c: char| !c.is_whitespace())
+            .map(|pos| body[..pos].rfind('\n').map_or(0, |nl| nl + 1))
+            .unwrap_or(body.len());
+        let raw_body = body[first_line..].to_string();
+        let body_line = self.source[..body_start + first_line].matches('\n').count() + 1;
+        
+        // Advance parser past all tokens until we hit the matching }
+        let mut depth = 1;
+        while depth > 0 {
+            match self.current_token() {
+                Token::LeftBrace => depth += 1,
+                Token::RightBrace => depth -= 1,
+                Token::EOF => return self.error("Unexpected EOF in foreign block"),
+                _ => {}
             }
+            self.advance();
         }
         
-        self.error("Could not extract foreign block body")
+        Ok((raw_body, body_line))
+    }
+    
+    /// Convert a token's (line, column) position into a byte offset in the source
+    fn source_offset(&self, (line, column): (usize, usize)) -> Option<usize> {
+        let mut offset = 0;
+        for (i, text) in self.source.split('\n').enumerate() {
+            if i == line {
+                return text.char_indices().nth(column).map(|(byte, _)| offset + byte);
+            }
+            offset += text.len() + 1;
+        }
+        None
     }
     
     fn parse_return_statement(&mut self) -> Result<Stmt, ParseError> {
@@ -366,7 +513,17 @@ impl Parser {
         let value = if *self.current_token() == Token::Semicolon || *self.current_token() == Token::EOF {
             None
         } else {
-            Some(self.parse_expression()?)
+            let first = self.parse_expression()?;
+            if *self.current_token() == Token::Comma {
+                // Multi-value return: `return a, b;`
+                let mut values = vec![first];
+                while self.match_token(&Token::Comma) {
+                    values.push(self.parse_expression()?);
+                }
+                Some(Expr::Tuple(values))
+            } else {
+                Some(first)
+            }
         };
         
         if self.match_token(&Token::Semicolon) {
@@ -381,7 +538,7 @@ impl Parser {
     }
 
     fn parse_assignment(&mut self) -> Result<Expr, ParseError> {
-        let expr = self.parse_equality()?;
+        let expr = self.parse_pipeline()?;
         
         if self.match_token(&Token::Assign) {
             let value = self.parse_assignment()?;
@@ -399,6 +556,24 @@ impl Parser {
         Ok(expr)
     }
     
+    fn parse_pipeline(&mut self) -> Result<Expr, ParseError> {
+        let first = self.parse_equality()?;
+        if *self.current_token() != Token::Pipe {
+            return Ok(first);
+        }
+        
+        let mut stages = vec![first];
+        while self.match_token(&Token::Pipe) {
+            let stage = self.parse_equality()?;
+            if !matches!(stage, Expr::FunctionCall { .. }) {
+                return self.error("Expected a block call after '|>'");
+            }
+            stages.push(stage);
+        }
+        
+        Ok(Expr::Pipeline(stages))
+    }
+    
     fn parse_equality(&mut self) -> Result<Expr, ParseError> {
         let mut left = self.parse_comparison()?;
         
@@ -535,6 +710,7 @@ impl Parser {
         match self.current_token().clone() {
             Token::If => self.parse_if_expression(),
             Token::While => self.parse_while_expression(),
+            Token::For => self.parse_for_expression(),
             Token::LeftBracket => self.parse_array_literal(),
             Token::LeftBrace => self.parse_map_literal(),
             Token::Number(n) => {
@@ -549,29 +725,26 @@ impl Parser {
                 self.advance();
                 Ok(Expr::StringLiteral(s))
             }
+            Token::BytesLiteral(b) => {
+                self.advance();
+                Ok(Expr::BytesLiteral(b))
+            }
+            // Conversions: string(b"...") and bytes("...")
+            Token::TypeString | Token::TypeBytes => {
+                let name = if *self.current_token() == Token::TypeString { "string" } else { "bytes" };
+                self.advance();
+                if *self.current_token() != Token::LeftParen {
+                    return self.error(&format!("Expected '(' after '{}' in an expression", name));
+                }
+                let arguments = self.parse_arguments()?;
+                Ok(Expr::FunctionCall { name: name.to_string(), arguments })
+            }
             Token::Identifier(name) => {
                 self.advance();
                 
                 // Check for function call
                 if *self.current_token() == Token::LeftParen {
-                    self.advance(); // consume '('
-                    
-                    let mut arguments = Vec::new();
-                    
-                    while *self.current_token() != Token::RightParen {
-                        arguments.push(self.parse_expression()?);
-                        
-                        if *self.current_token() == Token::Comma {
-                            self.advance();
-                        } else if *self.current_token() != Token::RightParen {
-                            return self.error("Expected ',' or ')' in argument list");
-                        }
-                    }
-                    
-                    if !self.match_token(&Token::RightParen) {
-                        return self.error("Expected ')' after arguments");
-                    }
-                    
+                    let arguments = self.parse_arguments()?;
                     Ok(Expr::FunctionCall { name, arguments })
                 } else {
                     Ok(Expr::Identifier(name))
@@ -617,6 +790,29 @@ impl Parser {
         }
     }
     
+    /// Parse a call's `(arg, ...)` list
+    fn parse_arguments(&mut self) -> Result<Vec<Expr>, ParseError> {
+        self.advance(); // consume '('
+        
+        let mut arguments = Vec::new();
+        
+        while *self.current_token() != Token::RightParen {
+            arguments.push(self.parse_expression()?);
+            
+            if *self.current_token() == Token::Comma {
+                self.advance();
+            } else if *self.current_token() != Token::RightParen {
+                return self.error("Expected ',' or ')' in argument list");
+            }
+        }
+        
+        if !self.match_token(&Token::RightParen) {
+            return self.error("Expected ')' after arguments");
+        }
+        
+        Ok(arguments)
+    }
+    
     fn parse_array_literal(&mut self) -> Result<Expr, ParseError> {
         self.advance(); // consume '['
         
@@ -717,6 +913,64 @@ impl Parser {
         })
     }
     
+    fn parse_for_expression(&mut self) -> Result<Expr, ParseError> {
+        self.advance(); // consume 'for'
+        
+        let variable = match self.current_token().clone() {
+            Token::Identifier(name) => name,
+            _ => return self.error("Expected loop variable after 'for'"),
+        };
+        self.advance();
+        
+        if !self.match_token(&Token::In) {
+            return self.error("Expected 'in' after loop variable");
+        }
+        
+        let iterable = self.parse_expression()?;
+        let body = self.parse_block_stmt()?;
+        
+        Ok(Expr::For {
+            variable,
+            iterable: Box::new(iterable),
+            body: Box::new(body),
+        })
+    }
+    
+    /// Parse `parallel { ... }`, whose statements must each be a block call,
+    /// optionally bound: `f(x);`, `int var a = f(x);`, `(int, int) var (a, b) = f(x);`
+    /// or `a = f(x);`
+    fn parse_parallel_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.advance(); // consume 'parallel'
+        if !self.match_token(&Token::LeftBrace) {
+            return self.error("Expected '{' after 'parallel'");
+        }
+        
+        let mut calls = Vec::new();
+        while *self.current_token() != Token::RightBrace && *self.current_token() != Token::EOF {
+            let (line, column) = self.current_pos();
+            let stmt = self.parse_statement()?;
+            let value = match &stmt {
+                Stmt::Expression(Expr::Assign { value, .. }) => Some(value.as_ref()),
+                Stmt::Expression(expr) => Some(expr),
+                Stmt::VariableDeclaration { value, .. } | Stmt::DestructuringDeclaration { value, .. } => Some(value),
+                _ => None,
+            };
+            if !matches!(value, Some(Expr::FunctionCall { name, .. }) if name != "speak") {
+                return Err(ParseError {
+                    message: "Expected a block call in 'parallel' (optionally assigned to a variable)".to_string(),
+                    line,
+                    column,
+                });
+            }
+            calls.push(stmt);
+        }
+        
+        if !self.match_token(&Token::RightBrace) {
+            return self.error("Expected '}' after parallel block");
+        }
+        Ok(Stmt::Parallel(calls))
+    }
+    
     fn parse_block_stmt(&mut self) -> Result<Stmt, ParseError> {
         if !self.match_token(&Token::LeftBrace) {
             return self.error("Expected '{' for block body");
@@ -735,3 +989,49 @@ impl Parser {
         Ok(Stmt::Block(body))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse `expression` as the initializer of a declaration (a statement
+    /// starting with `(` would be read as a tuple declaration)
+    fn parse_expression(expression: &str) -> Result<Expr, ParseError> {
+        let source = format!("bool var result = {};", expression);
+        let program = Parser::new(Lexer::new(&source)).parse()?;
+        match program.statements.as_slice() {
+            [Stmt::VariableDeclaration { value, .. }] => Ok(value.clone()),
+            other => panic!("expected one declaration, got {:?}", other),
+        }
+    }
+
+    fn identifier(name: &str) -> Expr {
+        Expr::Identifier(name.to_string())
+    }
+
+    fn call(name: &str, arguments: Vec<Expr>) -> Expr {
+        Expr::FunctionCall { name: name.to_string(), arguments }
+    }
+
+    fn equal(left: Expr, right: Expr) -> Expr {
+        Expr::Binary { left: Box::new(left), operator: BinaryOp::Equal, right: Box::new(right) }
+    }
+
+    #[test]
+    fn pipe_binds_more_loosely_than_equality() {
+        assert_eq!(
+            parse_expression("a == b |> f(c)").unwrap(),
+            Expr::Pipeline(vec![equal(identifier("a"), identifier("b")), call("f", vec![identifier("c")])])
+        );
+    }
+
+    #[test]
+    fn comparing_a_pipeline_needs_parentheses() {
+        let error = parse_expression("a |> f() == b").unwrap_err();
+        assert!(error.message.contains("Expected a block call after '|>'"), "{}", error);
+        assert_eq!(
+            parse_expression("(a |> f()) == b").unwrap(),
+            equal(Expr::Pipeline(vec![identifier("a"), call("f", Vec::new())]), identifier("b"))
+        );
+    }
+}
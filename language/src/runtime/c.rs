@@ -0,0 +1,450 @@
+// runtime/c.rs
+// #c blocks: wrapper generation, compilation with the system C compiler and execution
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::ast::HybridType;
+
+use super::channel;
+use super::foreign::{Callbacks, ForeignRuntime, Validation};
+use super::info::{self, Capabilities, RuntimeInfo};
+use super::manager::{ForeignBlock, RuntimeError, RuntimeErrorKind};
+use super::process::{self, Outcome, ResourceLimits};
+use super::rust::Wrapper;
+use super::source_map;
+use super::workspace;
+
+/// Environment variable selecting the C compiler (defaults to `cc`)
+pub const COMPILER_ENV: &str = "CC";
+
+/// Support code compiled into every wrapper: readers for the JSON array of
+/// scalars, strings and flat arrays that arrives on stdin, and a JSON
+/// string writer.
+const PRELUDE: &str = r#"
+#include <math.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+static void __hybrid_fail(const char *what) {
+    fprintf(stderr, "hybrid: invalid JSON for %s\n", what);
+    exit(2);
+}
+
+static const char *__hybrid_ws(const char *s) {
+    while (*s == ' ' || *s == '\t' || *s == '\n' || *s == '\r') s++;
+    return s;
+}
+
+static char *__hybrid_read_stdin(void) {
+    size_t cap = 4096, n = 0, got;
+    char *buf = malloc(cap);
+    while ((got = fread(buf + n, 1, cap - n - 1, stdin)) > 0) {
+        n += got;
+        if (n + 1 == cap) buf = realloc(buf, cap *= 2);
+    }
+    buf[n] = '\0';
+    return buf;
+}
+
+/* Step past the '[', ',' or ']' around an argument in the argument array */
+static void __hybrid_expect(const char **p, char c, const char *name, int count) {
+    const char *s = __hybrid_ws(*p);
+    if (*s != c) {
+        fprintf(stderr, "hybrid: block '%s' expects %d arguments\n", name, count);
+        exit(2);
+    }
+    *p = s + 1;
+}
+
+static long long __hybrid_int(const char **p, const char *what) {
+    const char *s = __hybrid_ws(*p);
+    char *end;
+    long long value = strtoll(s, &end, 10);
+    if (*end == '.' || *end == 'e' || *end == 'E') {
+        /* Hybrid numbers are floats, so `3.0` is still an int; `2.5` is not */
+        double d = strtod(s, &end);
+        if (!isfinite(d) || d != trunc(d)) {
+            fprintf(stderr, "hybrid: %s is not an int\n", what);
+            exit(2);
+        }
+        value = (long long)d;
+    }
+    if (end == s) __hybrid_fail(what);
+    *p = end;
+    return value;
+}
+
+static double __hybrid_float(const char **p, const char *what) {
+    const char *s = __hybrid_ws(*p);
+    char *end;
+    double value = strtod(s, &end);
+    if (end == s) __hybrid_fail(what);
+    *p = end;
+    return value;
+}
+
+static bool __hybrid_bool(const char **p, const char *what) {
+    const char *s = __hybrid_ws(*p);
+    if (strncmp(s, "true", 4) == 0) { *p = s + 4; return true; }
+    if (strncmp(s, "false", 5) == 0) { *p = s + 5; return false; }
+    __hybrid_fail(what);
+    return false;
+}
+
+static unsigned __hybrid_hex4(const char *s, const char *what) {
+    unsigned value = 0;
+    for (int i = 0; i < 4; i++) {
+        char c = s[i];
+        value <<= 4;
+        if (c >= '0' && c <= '9') value |= (unsigned)(c - '0');
+        else if (c >= 'a' && c <= 'f') value |= (unsigned)(c - 'a' + 10);
+        else if (c >= 'A' && c <= 'F') value |= (unsigned)(c - 'A' + 10);
+        else __hybrid_fail(what);
+    }
+    return value;
+}
+
+static char *__hybrid_utf8(char *o, unsigned cp) {
+    if (cp < 0x80) { *o++ = (char)cp; }
+    else if (cp < 0x800) { *o++ = (char)(0xC0 | (cp >> 6)); *o++ = (char)(0x80 | (cp & 0x3F)); }
+    else if (cp < 0x10000) { *o++ = (char)(0xE0 | (cp >> 12)); *o++ = (char)(0x80 | ((cp >> 6) & 0x3F)); *o++ = (char)(0x80 | (cp & 0x3F)); }
+    else { *o++ = (char)(0xF0 | (cp >> 18)); *o++ = (char)(0x80 | ((cp >> 12) & 0x3F)); *o++ = (char)(0x80 | ((cp >> 6) & 0x3F)); *o++ = (char)(0x80 | (cp & 0x3F)); }
+    return o;
+}
+
+/* Decoded text is never longer than its JSON form, so strlen(s) + 1 bytes suffice */
+static char *__hybrid_string(const char **p, const char *what) {
+    const char *s = __hybrid_ws(*p);
+    if (*s++ != '"') __hybrid_fail(what);
+    char *out = malloc(strlen(s) + 1), *o = out;
+    while (*s && *s != '"') {
+        if (*s != '\\') { *o++ = *s++; continue; }
+        s++;
+        switch (*s++) {
+            case '"': *o++ = '"'; break;
+            case '\\': *o++ = '\\'; break;
+            case '/': *o++ = '/'; break;
+            case 'b': *o++ = '\b'; break;
+            case 'f': *o++ = '\f'; break;
+            case 'n': *o++ = '\n'; break;
+            case 'r': *o++ = '\r'; break;
+            case 't': *o++ = '\t'; break;
+            case 'u': {
+                unsigned cp = __hybrid_hex4(s, what);
+                s += 4;
+                if (cp >= 0xD800 && cp < 0xDC00 && s[0] == '\\' && s[1] == 'u') {
+                    unsigned low = __hybrid_hex4(s + 2, what);
+                    s += 6;
+                    cp = 0x10000 + ((cp - 0xD800) << 10) + (low - 0xDC00);
+                }
+                o = __hybrid_utf8(o, cp);
+                break;
+            }
+            default: __hybrid_fail(what);
+        }
+    }
+    if (*s != '"') __hybrid_fail(what);
+    *o = '\0';
+    *p = s + 1;
+    return out;
+}
+
+#define __HYBRID_ARRAY(NAME, T, ELEM) \
+static T *NAME(const char **p, const char *what, size_t *len) { \
+    const char *s = __hybrid_ws(*p); \
+    size_t cap = 8, n = 0; \
+    T *items = malloc(cap * sizeof(T)); \
+    if (*s++ != '[') __hybrid_fail(what); \
+    s = __hybrid_ws(s); \
+    if (*s == ']') { s++; } else { \
+        for (;;) { \
+            if (n == cap) items = realloc(items, (cap *= 2) * sizeof(T)); \
+            items[n++] = ELEM(&s, what); \
+            s = __hybrid_ws(s); \
+            if (*s == ',') { s++; continue; } \
+            if (*s++ != ']') __hybrid_fail(what); \
+            break; \
+        } \
+    } \
+    *p = s; \
+    *len = n; \
+    return items; \
+}
+
+__HYBRID_ARRAY(__hybrid_int_array, long long, __hybrid_int)
+__HYBRID_ARRAY(__hybrid_float_array, double, __hybrid_float)
+__HYBRID_ARRAY(__hybrid_bool_array, bool, __hybrid_bool)
+__HYBRID_ARRAY(__hybrid_string_array, char *, __hybrid_string)
+
+static void __hybrid_print_string(const char *s) {
+    putchar('"');
+    for (; *s; s++) {
+        unsigned char c = (unsigned char)*s;
+        if (c == '"' || c == '\\') { putchar('\\'); putchar(c); }
+        else if (c == '\n') fputs("\\n", stdout);
+        else if (c == '\r') fputs("\\r", stdout);
+        else if (c == '\t') fputs("\\t", stdout);
+        else if (c < 0x20) printf("\\u%04x", c);
+        else putchar(c);
+    }
+    putchar('"');
+}
+
+static void __hybrid_print_float(double value) {
+    if (isfinite(value)) printf("%.17g", value);
+    else fputs("null", stdout);
+}
+"#;
+
+/// Runs `#c` blocks as binaries built by the system C compiler, one process per call
+#[derive(Default)]
+pub struct CRuntime {
+    /// Block name -> (wrapper source, binary) of the last build
+    prepared: HashMap<String, (String, PathBuf)>,
+}
+
+impl CRuntime {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The compiler command (`$CC`, or `cc`)
+    pub fn compiler() -> String {
+        std::env::var(COMPILER_ENV)
+            .ok()
+            .filter(|cc| !cc.trim().is_empty())
+            .unwrap_or_else(|| "cc".to_string())
+    }
+
+    /// First line of `<compiler> --version`, queried once per process
+    pub fn compiler_version() -> Option<String> {
+        info::version(Path::new(&Self::compiler()))
+    }
+
+    /// Compile a generated wrapper (or find it in the cache), with
+    /// diagnostics located in the `.hyb` file
+    fn build(block: &ForeignBlock, wrapper: &Wrapper) -> Result<PathBuf, RuntimeError> {
+        let compiler = Self::compiler();
+        let toolchain = format!("{} {}", compiler, Self::compiler_version().unwrap_or_default());
+        let files = [("block.c", wrapper.source.as_str())];
+        workspace::build_cached("c", &block.name, &toolchain, &files, |dir| {
+            let output = dir.join(&format!("block{}", std::env::consts::EXE_SUFFIX));
+            let mut command = Command::new(&compiler);
+            command.arg("-O2").arg("-std=c99").arg(dir.join("block.c")).arg("-o").arg(&output).arg("-lm");
+            (command, output)
+        })
+        .map_err(|e| map_compile_error(e, block, wrapper))
+    }
+}
+
+/// Point a compile error's diagnostics at the `.hyb` file instead of the
+/// wrapper. This happens here rather than with `#line` directives so the
+/// wrapper, and with it the cache key, does not depend on where the block
+/// sits in its file.
+fn map_compile_error(error: RuntimeError, block: &ForeignBlock, wrapper: &Wrapper) -> RuntimeError {
+    let RuntimeErrorKind::Compile { diagnostics } = &*error.kind else {
+        return error;
+    };
+    let body_len = block.code.trim_end().lines().count();
+    let diagnostics = source_map::rewrite_wrapper_refs(diagnostics, "block.c", wrapper.body_start, body_len, 0, &block.origin);
+    let location = source_map::first_location(&diagnostics, &block.origin.file);
+    RuntimeError::compile("c", diagnostics).with_location(location)
+}
+
+impl ForeignRuntime for CRuntime {
+    fn language(&self) -> &str {
+        "c"
+    }
+
+    fn info(&self) -> RuntimeInfo {
+        let capabilities = Capabilities {
+            validation: true,
+            parallel: true,
+            ..Capabilities::default()
+        };
+        RuntimeInfo::probe("c", Self::compiler(), capabilities)
+    }
+
+    fn prepare(&mut self, block: &ForeignBlock) -> Result<(), RuntimeError> {
+        let wrapper = generate_wrapper(block).map_err(|message| RuntimeError::compile("c", message))?;
+        if self.prepared.get(&block.name).is_some_and(|(built, _)| *built == wrapper.source) {
+            return Ok(());
+        }
+
+        let binary_path = Self::build(block, &wrapper)?;
+        self.prepared.insert(block.name.clone(), (wrapper.source, binary_path));
+        Ok(())
+    }
+
+    fn fork(&self) -> Option<Box<dyn ForeignRuntime>> {
+        Some(Box::new(CRuntime { prepared: self.prepared.clone() }))
+    }
+
+    /// Compile the block ahead of time into the cache
+    fn validator(&self, block: &ForeignBlock) -> Option<Validation> {
+        let block = block.clone();
+        Some(Box::new(move || {
+            let wrapper = generate_wrapper(&block).map_err(|message| RuntimeError::compile("c", message))?;
+            Self::build(&block, &wrapper).map(|_| ())
+        }))
+    }
+
+    fn invoke(
+        &mut self,
+        block: &ForeignBlock,
+        args: &[serde_json::Value],
+        limits: &ResourceLimits,
+        _callbacks: &mut dyn Callbacks,
+    ) -> Result<serde_json::Value, RuntimeError> {
+        let (_, binary_path) = self
+            .prepared
+            .get(&block.name)
+            .ok_or_else(|| RuntimeError::new("c", format!("Block '{}' has not been compiled", block.name)))?;
+
+        // Arguments go on stdin as a JSON array, since argv has a size limit
+        let command = Command::new(binary_path);
+        let outcome = process::run_framed(command, serde_json::Value::from(args.to_vec()).to_string(), limits)
+            .map_err(|e| RuntimeError::new("c", format!("Failed to run binary: {}", e)))?;
+
+        match outcome {
+            Outcome::TimedOut => Err(RuntimeError::timeout("c", &block.name, limits.timeout.unwrap_or_default())),
+            Outcome::Exited { status, stderr, .. } if limits.explains(&status, &stderr) => {
+                Err(RuntimeError::resource_limit("c", &block.name, limits))
+            }
+            Outcome::Exited { status, .. } if !status.success() => {
+                Err(RuntimeError::non_zero_exit("c", &block.name, status, ""))
+            }
+            Outcome::Exited { frame: Some(json), .. } => serde_json::from_str(&json)
+                .map_err(|e| RuntimeError::decode("c", format!("Failed to parse result: {}", e))),
+            Outcome::Exited { frame: None, .. } => {
+                Err(RuntimeError::new("c", "Block exited without producing a result"))
+            }
+        }
+    }
+}
+
+/// Generate the C source for a block: the body becomes a `static` function
+/// taking the typed parameters (arrays also get a `<name>_len` count), and
+/// `main` decodes the JSON argument array read from stdin, calls it and
+/// prints the framed JSON result.
+pub fn generate_wrapper(block: &ForeignBlock) -> Result<Wrapper, String> {
+    let mut signature = Vec::new();
+    let mut decode = Vec::new();
+    let mut call_args = Vec::new();
+    for (i, param) in block.parameters.iter().enumerate() {
+        let what = format!("parameter '{}'", param.name);
+        let (c_type, reader) = c_param(&param.param_type)
+            .ok_or_else(|| format!("C blocks do not support parameter '{}' of type {:?}", param.name, param.param_type))?;
+        if i > 0 {
+            decode.push(format!("    __hybrid_expect(&__cursor, ',', \"{}\", {});", block.name, block.parameters.len()));
+        }
+        if matches!(param.param_type, HybridType::Array(_)) {
+            decode.push(format!(
+                "    size_t {name}_len;\n    {ty} {name} = {reader}(&__cursor, \"{what}\", &{name}_len);",
+                name = param.name, ty = c_type, reader = reader, what = what,
+            ));
+            signature.push(format!("{} {}", c_type, param.name));
+            signature.push(format!("size_t {}_len", param.name));
+            call_args.push(param.name.clone());
+            call_args.push(format!("{}_len", param.name));
+        } else {
+            decode.push(format!(
+                "    {ty} {name} = {reader}(&__cursor, \"{what}\");",
+                name = param.name, ty = c_type, reader = reader, what = what,
+            ));
+            signature.push(format!("{} {}", c_type, param.name));
+            call_args.push(param.name.clone());
+        }
+    }
+
+    let (return_type, emit) = match block.return_types.as_slice() {
+        [] | [HybridType::Void] => ("void", "fputs(\"null\", stdout);"),
+        [HybridType::Int] => ("long long", "printf(\"%lld\", __result);"),
+        [HybridType::Float] => ("double", "__hybrid_print_float(__result);"),
+        [HybridType::Bool] => ("bool", "fputs(__result ? \"true\" : \"false\", stdout);"),
+        [HybridType::String] => ("const char *", "__hybrid_print_string(__result);"),
+        [other] => return Err(format!("C blocks cannot return {:?}; use int, float, bool or string", other)),
+        _ => return Err("C blocks support a single return value".to_string()),
+    };
+    let call = if return_type == "void" {
+        format!("__hybrid_block({});", call_args.join(", "))
+    } else {
+        format!("{} __result = __hybrid_block({});", return_type, call_args.join(", "))
+    };
+
+    let head = format!(
+        "{prelude}\nstatic {return_type} __hybrid_block({signature}) {{\n",
+        prelude = PRELUDE,
+        return_type = return_type,
+        signature = if signature.is_empty() { "void".to_string() } else { signature.join(", ") },
+    );
+    let code = block.code.trim_end();
+
+    let source = format!(r#"{head}{code}
+}}
+
+int main(void) {{
+    const char *__cursor = __hybrid_read_stdin();
+    __hybrid_expect(&__cursor, '[', "{name}", {count});
+{decode}
+    __hybrid_expect(&__cursor, ']', "{name}", {count});
+    {call}
+
+    // Block output goes first so it never lands inside the result frame
+    fflush(stdout);
+    fputs("{marker}", stdout);
+    {emit}
+    putchar('\n');
+    return 0;
+}}
+"#,
+        head = head,
+        code = code,
+        name = block.name,
+        count = block.parameters.len(),
+        decode = decode.join("\n"),
+        marker = c_escape(channel::RESULT_MARKER),
+        call = call,
+        emit = emit,
+    );
+
+    Ok(Wrapper {
+        body_start: head.lines().count() + 1,
+        source,
+    })
+}
+
+/// C parameter type and JSON reader for a Hybrid type
+fn c_param(ty: &HybridType) -> Option<(&'static str, &'static str)> {
+    Some(match ty {
+        HybridType::Int => ("long long", "__hybrid_int"),
+        HybridType::Float => ("double", "__hybrid_float"),
+        HybridType::Bool => ("bool", "__hybrid_bool"),
+        HybridType::String => ("char *", "__hybrid_string"),
+        HybridType::Array(inner) => match inner.as_ref() {
+            HybridType::Int => ("long long *", "__hybrid_int_array"),
+            HybridType::Float => ("double *", "__hybrid_float_array"),
+            HybridType::Bool => ("bool *", "__hybrid_bool_array"),
+            HybridType::String => ("char **", "__hybrid_string_array"),
+            _ => return None,
+        },
+        _ => return None,
+    })
+}
+
+/// Escape text for a C string literal (octal escapes for control characters)
+fn c_escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '"' => "\\\"".to_string(),
+            '\\' => "\\\\".to_string(),
+            c if (c as u32) < 0x20 => format!("\\{:03o}", c as u32),
+            c => c.to_string(),
+        })
+        .collect()
+}
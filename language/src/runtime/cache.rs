@@ -0,0 +1,162 @@
+// runtime/cache.rs
+// On-disk cache for compiled foreign block artifacts
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Environment variable that overrides the cache location
+pub const CACHE_DIR_ENV: &str = "HYBRID_CACHE_DIR";
+
+/// Languages that keep artifacts in the cache, one directory each. Only
+/// these directories are listed or removed, since `$HYBRID_CACHE_DIR` may
+/// name a directory that holds other files too.
+pub const LANGUAGES: &[&str] = &["c", "rust"];
+
+/// A single cached artifact
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub language: String,
+    pub key: String,
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// Root directory of the Hybrid cache (`$HYBRID_CACHE_DIR`, or the user cache dir)
+pub fn cache_root() -> PathBuf {
+    if let Some(dir) = std::env::var_os(CACHE_DIR_ENV) {
+        return PathBuf::from(dir);
+    }
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("hybrid")
+}
+
+/// Directory holding the artifacts of one language, created on demand
+pub fn language_dir(language: &str) -> io::Result<PathBuf> {
+    let dir = cache_root().join(language);
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Stable content hash used as a cache key.
+///
+/// FNV-1a (64-bit) over every part, with a separator so that
+/// `["ab", "c"]` and `["a", "bc"]` hash differently. Unlike `DefaultHasher`
+/// the result does not change between Hybrid builds, so keys stay valid
+/// across `hybrid` upgrades.
+pub fn content_hash(parts: &[&str]) -> String {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let mut hash = OFFSET;
+    for part in parts {
+        for byte in part.bytes().chain(std::iter::once(0xff)) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(PRIME);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+/// List every cached artifact, grouped by language directory
+pub fn entries() -> io::Result<Vec<CacheEntry>> {
+    entries_in(&cache_root())
+}
+
+fn entries_in(root: &Path) -> io::Result<Vec<CacheEntry>> {
+    let mut entries = Vec::new();
+    for language in LANGUAGES {
+        let lang_dir = root.join(language);
+        if !lang_dir.is_dir() {
+            continue;
+        }
+        for artifact in fs::read_dir(&lang_dir)? {
+            let artifact = artifact?;
+            let path = artifact.path();
+            entries.push(CacheEntry {
+                language: language.to_string(),
+                key: artifact.file_name().to_string_lossy().to_string(),
+                size: dir_size(&path)?,
+                path,
+            });
+        }
+    }
+
+    entries.sort_by(|a, b| (&a.language, &a.key).cmp(&(&b.language, &b.key)));
+    Ok(entries)
+}
+
+/// Remove every language's artifact directory, returning the number of
+/// bytes freed. Anything else in the cache root is left alone, and the root
+/// itself is only removed once it is empty.
+pub fn clear() -> io::Result<u64> {
+    clear_in(&cache_root())
+}
+
+fn clear_in(root: &Path) -> io::Result<u64> {
+    let mut freed = 0;
+    for language in LANGUAGES {
+        let lang_dir = root.join(language);
+        if !lang_dir.is_dir() {
+            continue;
+        }
+        freed += dir_size(&lang_dir)?;
+        fs::remove_dir_all(&lang_dir)?;
+    }
+    // Fails (harmlessly) unless the root is now empty
+    let _ = fs::remove_dir(root);
+    Ok(freed)
+}
+
+fn dir_size(path: &Path) -> io::Result<u64> {
+    let meta = fs::symlink_metadata(path)?;
+    if !meta.is_dir() {
+        return Ok(meta.len());
+    }
+    let mut total = 0;
+    for child in fs::read_dir(path)? {
+        total += dir_size(&child?.path())?;
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_hash_is_stable() {
+        // Pinned: a different value would invalidate every user's cache
+        assert_eq!(content_hash(&[]), "cbf29ce484222325");
+        assert_eq!(content_hash(&["fn main() {}"]), "35554ed2db8e2ad0");
+    }
+
+    #[test]
+    fn content_hash_changes_with_code_and_toolchain() {
+        let key = content_hash(&["cc -O2 -std=c99", "block.c", "int main(void) { return 0; }"]);
+        assert_ne!(key, content_hash(&["cc -O2 -std=c99", "block.c", "int main(void) { return 1; }"]));
+        assert_ne!(key, content_hash(&["cc -O0 -std=c99", "block.c", "int main(void) { return 0; }"]));
+        assert_ne!(content_hash(&["ab", "c"]), content_hash(&["a", "bc"]));
+    }
+
+    #[test]
+    fn clear_removes_only_language_dirs() {
+        let root = std::env::temp_dir().join(format!("hybrid-cache-test-{}", std::process::id()));
+        fs::create_dir_all(root.join("rust").join("0123")).unwrap();
+        fs::write(root.join("rust").join("0123").join("block"), b"binary").unwrap();
+        fs::create_dir_all(root.join("notes")).unwrap();
+        fs::write(root.join("notes").join("todo.txt"), b"keep me").unwrap();
+
+        let entries = entries_in(&root).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].language.as_str(), entries[0].key.as_str(), entries[0].size), ("rust", "0123", 6));
+
+        assert_eq!(clear_in(&root).unwrap(), 6);
+        assert!(!root.join("rust").exists());
+        assert_eq!(fs::read(root.join("notes").join("todo.txt")).unwrap(), b"keep me");
+
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(clear_in(&root).unwrap(), 0);
+    }
+}
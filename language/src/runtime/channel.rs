@@ -0,0 +1,47 @@
+// runtime/channel.rs
+// Result framing on foreign process stdout
+
+use std::io::{self, BufRead, Write};
+
+/// Prefix of a framed result line. Everything else a foreign process writes
+/// to stdout is user output and is forwarded to Hybrid's stdout as-is.
+pub const RESULT_MARKER: &str = "\u{1e}hybrid-result:";
+
+/// Read `reader` until the next framed result line, forwarding all user
+/// output before it to our stdout as it arrives.
+///
+/// Returns the payload after the marker, or `None` if the stream ended
+/// without a frame.
+pub fn forward_until_frame(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let text = String::from_utf8_lossy(&line);
+        // User output without a trailing newline shares a line with the frame
+        if let Some(pos) = text.find(RESULT_MARKER) {
+            forward(text[..pos].as_bytes())?;
+            let payload = text[pos + RESULT_MARKER.len()..].trim_end_matches(['\n', '\r']);
+            return Ok(Some(payload.to_string()));
+        }
+        forward(&line)?;
+    }
+}
+
+/// Forward whatever is left on `reader` (output after the last frame)
+pub fn forward_rest(reader: &mut impl BufRead) -> io::Result<()> {
+    while forward_until_frame(reader)?.is_some() {}
+    Ok(())
+}
+
+fn forward(bytes: &[u8]) -> io::Result<()> {
+    if bytes.is_empty() {
+        return Ok(());
+    }
+    let mut out = io::stdout().lock();
+    out.write_all(bytes)?;
+    out.flush()
+}
@@ -0,0 +1,141 @@
+// runtime/foreign.rs
+// The ForeignRuntime trait implemented by every #lang backend
+
+use super::info::RuntimeInfo;
+use super::manager::{ForeignBlock, RuntimeError};
+use super::process::ResourceLimits;
+use super::source_map::SourceLocation;
+
+/// Identifies a stream a runtime opened with `ForeignRuntime::open_stream`
+pub type StreamId = u64;
+
+/// A self-contained pre-flight check of one block (see
+/// `ForeignRuntime::validator`)
+pub type Validation = Box<dyn FnOnce() -> Result<(), RuntimeError> + Send>;
+
+/// A backend that runs `#lang` blocks for one language.
+///
+/// Runtimes are registered with `RuntimeManager::register` (or
+/// `Evaluator::register_runtime`) under the name used after `#`. Values cross
+/// the boundary as JSON: `invoke` receives one `serde_json::Value` per
+/// argument and returns the block's result as a single JSON value, which the
+/// manager converts back and splits for multi-value returns.
+pub trait ForeignRuntime: Send {
+    /// Language name as written after `#` (e.g. `"python"`)
+    fn language(&self) -> &str;
+
+    /// Other names accepted after `#` (e.g. `"js"` for `"javascript"`)
+    fn aliases(&self) -> &[&str] {
+        &[]
+    }
+
+    /// The toolchain this runtime needs and what its blocks support; the
+    /// runtime is unavailable if `version` is `None`.
+    ///
+    /// Called when the language is first used (at most once per manager),
+    /// not when the runtime is registered. `RuntimeInfo::probe` runs
+    /// `--version` once per process.
+    fn info(&self) -> RuntimeInfo;
+
+    /// Get a block ready to run (compile it, check it, ...).
+    ///
+    /// Called before every `invoke`, so implementations should remember
+    /// blocks they have already prepared.
+    fn prepare(&mut self, block: &ForeignBlock) -> Result<(), RuntimeError> {
+        let _ = block;
+        Ok(())
+    }
+
+    /// A check of `block` for pre-flight validation (compiling or
+    /// byte-compiling it without running it), or `None` if there is nothing
+    /// to check. Checks of different blocks run in parallel on worker
+    /// threads, so the job must own everything it needs.
+    fn validator(&self, block: &ForeignBlock) -> Option<Validation> {
+        let _ = block;
+        None
+    }
+
+    /// An independent copy of this runtime (with its own worker processes)
+    /// for running calls in parallel with this one, or `None` if calls must
+    /// go through this instance one at a time. The copy is shut down when
+    /// its call returns.
+    fn fork(&self) -> Option<Box<dyn ForeignRuntime>> {
+        None
+    }
+
+    /// Run a `#lang setup { ... }` block in the namespace shared by all of
+    /// this language's blocks. Runtimes without shared state reject it.
+    fn setup(
+        &mut self,
+        code: &str,
+        origin: &SourceLocation,
+        limits: &ResourceLimits,
+        callbacks: &mut dyn Callbacks,
+    ) -> Result<(), RuntimeError> {
+        let _ = (code, origin, limits, callbacks);
+        Err(RuntimeError::new(
+            self.language(),
+            format!("#{} does not support setup blocks", self.language()),
+        ))
+    }
+
+    /// Run `block` with the given arguments under `limits`. Runtimes whose
+    /// blocks can call native Hybrid blocks route those calls to `callbacks`.
+    fn invoke(
+        &mut self,
+        block: &ForeignBlock,
+        args: &[serde_json::Value],
+        limits: &ResourceLimits,
+        callbacks: &mut dyn Callbacks,
+    ) -> Result<serde_json::Value, RuntimeError>;
+
+    /// Start an `iter[T]` block, whose items are then pulled one at a time
+    /// with `next_item`. Runtimes that can't stream reject it.
+    fn open_stream(
+        &mut self,
+        block: &ForeignBlock,
+        args: &[serde_json::Value],
+        limits: &ResourceLimits,
+        callbacks: &mut dyn Callbacks,
+    ) -> Result<StreamId, RuntimeError> {
+        let _ = (args, limits, callbacks);
+        Err(RuntimeError::compile(
+            self.language(),
+            format!("Block '{}': #{} blocks cannot return iter[...]", block.name, self.language()),
+        ))
+    }
+
+    /// The next item of an open stream, or `None` once it is exhausted.
+    /// `limits.timeout` given to `open_stream` bounds the wait for each item.
+    fn next_item(&mut self, stream: StreamId, callbacks: &mut dyn Callbacks) -> Result<Option<serde_json::Value>, RuntimeError> {
+        let _ = (stream, callbacks);
+        Err(RuntimeError::new(self.language(), "No stream is open"))
+    }
+
+    /// Stop a stream early (or release an exhausted one); unknown ids are ignored
+    fn close_stream(&mut self, stream: StreamId) {
+        let _ = stream;
+    }
+
+    /// Release processes or other resources held by the runtime
+    fn shutdown(&mut self) {}
+}
+
+/// Native Hybrid blocks a running foreign block can call back into
+/// (`hybrid.call("name", ...)` in Python). A native block reached this way
+/// cannot call foreign blocks itself.
+pub trait Callbacks {
+    /// Call the native block `name` with JSON arguments, returning its JSON
+    /// result or an error message for the foreign side to raise
+    fn call(&mut self, name: &str, args: Vec<serde_json::Value>) -> Result<serde_json::Value, String>;
+}
+
+/// `Callbacks` for blocks run outside an `Evaluator`, where no native
+/// blocks exist
+pub struct NoCallbacks;
+
+impl Callbacks for NoCallbacks {
+    fn call(&mut self, name: &str, _args: Vec<serde_json::Value>) -> Result<serde_json::Value, String> {
+        Err(format!("Cannot call '{}': no native blocks are available", name))
+    }
+}
@@ -0,0 +1,90 @@
+// runtime/info.rs
+// Toolchain detection shared by the runtimes, `hybrid doctor` and the LSP
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+
+/// What a runtime runs its blocks with and which features they can use,
+/// as reported by `ForeignRuntime::info`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeInfo {
+    pub language: String,
+    /// The interpreter or compiler blocks run on (e.g. `python3`, `cc`)
+    pub path: PathBuf,
+    /// Its `--version` output, or `None` if it isn't installed
+    pub version: Option<String>,
+    pub capabilities: Capabilities,
+}
+
+/// Optional features of a runtime's blocks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// `#lang setup { ... }` blocks
+    pub setup: bool,
+    /// Calls back into native Hybrid blocks
+    pub callbacks: bool,
+    /// `iter[T]` blocks streamed into `for` loops
+    pub streaming: bool,
+    /// Pre-flight checks before the program runs
+    pub validation: bool,
+    /// Forked instances for `parallel { ... }`
+    pub parallel: bool,
+}
+
+impl RuntimeInfo {
+    /// Info for `path`, probing its version with `path --version`
+    pub fn probe(language: &str, path: impl Into<PathBuf>, capabilities: Capabilities) -> Self {
+        let path = path.into();
+        RuntimeInfo {
+            language: language.to_string(),
+            version: version(&path),
+            path,
+            capabilities,
+        }
+    }
+
+    /// Whether the toolchain is installed
+    pub fn is_available(&self) -> bool {
+        self.version.is_some()
+    }
+}
+
+impl Capabilities {
+    /// Names of the supported features, for display
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            (self.setup, "setup"),
+            (self.callbacks, "callbacks"),
+            (self.streaming, "streaming"),
+            (self.validation, "validation"),
+            (self.parallel, "parallel"),
+        ]
+        .into_iter()
+        .filter_map(|(supported, name)| supported.then_some(name))
+        .collect()
+    }
+}
+
+/// First line of `program --version` (stdout, or stderr for programs such
+/// as Python 2 that print it there), or `None` if it doesn't run. Each
+/// program is only run once per process.
+pub fn version(program: &Path) -> Option<String> {
+    static VERSIONS: OnceLock<Mutex<HashMap<PathBuf, Option<String>>>> = OnceLock::new();
+    let mut versions = VERSIONS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+    versions
+        .entry(program.to_path_buf())
+        .or_insert_with(|| {
+            Command::new(program)
+                .arg("--version")
+                .output()
+                .ok()
+                .filter(|o| o.status.success())
+                .and_then(|o| {
+                    let text = if o.stdout.is_empty() { o.stderr } else { o.stdout };
+                    String::from_utf8_lossy(&text).lines().next().map(|line| line.trim().to_string())
+                })
+        })
+        .clone()
+}
@@ -0,0 +1,154 @@
+// runtime/javascript.rs
+// #javascript / #js blocks executed by Node.js
+
+use std::process::Command;
+
+use super::channel;
+use super::foreign::{Callbacks, ForeignRuntime};
+use super::info::{Capabilities, RuntimeInfo};
+use super::manager::{ForeignBlock, RuntimeError};
+use super::process::{self, Outcome, ResourceLimits};
+use super::source_map;
+
+/// The launcher run with `node -e`. `process.argv[1]` is the frame marker
+/// and stdin holds a JSON array `[name, params, code, file, line, args]`
+/// (argv has a size limit, stdin does not). The block body becomes an async
+/// function (so it may `await`), and a single framed line
+/// `{"ok": true, "result": ...}` or `{"ok": false, "error": "<stack>"}` is
+/// written when it settles, mirroring the Python worker's responses.
+/// `bytes` (`{"$bytes": "<base64>"}`) arrive as `Buffer`s, and `Buffer` or
+/// other `Uint8Array` results are sent back as bytes.
+const LAUNCHER_SOURCE: &str = r#"
+const __marker = process.argv[1];
+const __fromJson = (key, value) =>
+    value && typeof value === "object" && !Array.isArray(value) && Object.keys(value).length === 1 && typeof value.$bytes === "string"
+        ? Buffer.from(value.$bytes, "base64")
+        : value;
+function __toJson(key, value) {
+    const raw = this[key];
+    return raw instanceof Uint8Array
+        ? { $bytes: Buffer.from(raw.buffer, raw.byteOffset, raw.byteLength).toString("base64") }
+        : value;
+}
+const __AsyncFunction = Object.getPrototypeOf(async function () {}).constructor;
+let name, params, code, file, line, args;
+(async () => {
+    let response;
+    let phase = "compile";
+    try {
+        [name, params, code, file, line, args] = JSON.parse(require("fs").readFileSync(0, "utf8"), __fromJson);
+        const fn = new __AsyncFunction(...params, code + "\n//# sourceURL=<hybrid:" + name + ">");
+        phase = "run";
+        const result = await fn(...args);
+        response = JSON.stringify({ ok: true, result: result === undefined ? null : result }, __toJson);
+    } catch (err) {
+        // Keep the message and the block's own frames, not the launcher's,
+        // pointing them at the .hyb file (the body starts on the function's third line)
+        const source = "<hybrid:" + name + ">:";
+        const stack = ((err && err.stack) || String(err)).split("\n");
+        const error = [stack[0], ...stack.slice(1).filter(l => l.includes(source))]
+            .join("\n")
+            .split(source)
+            .map((part, i) => i === 0 ? part : part.replace(/^(\d+)/, n => file + ":" + (Number(n) - 3 + line)))
+            .join("");
+        const type = (err && err.name) || "Error";
+        const message = err && err.message !== undefined ? String(err.message) : String(err);
+        response = JSON.stringify({ ok: false, error, type, message, phase });
+    }
+    process.stdout.write(__marker + response + "\n");
+})();
+"#;
+
+/// Runs JavaScript blocks in a fresh `node` process per call
+#[derive(Default)]
+pub struct JavaScriptRuntime;
+
+impl JavaScriptRuntime {
+    pub fn new() -> Self {
+        JavaScriptRuntime
+    }
+
+    /// The block's result, or the error for what it threw: a compile error
+    /// if the body failed to parse, an exception otherwise
+    fn into_result(response: serde_json::Value, block: &ForeignBlock) -> Result<serde_json::Value, RuntimeError> {
+        let serde_json::Value::Object(mut fields) = response else {
+            return Err(RuntimeError::decode("javascript", format!("Malformed launcher response: {}", response)));
+        };
+        if fields.get("ok").and_then(|ok| ok.as_bool()) == Some(true) {
+            return Ok(fields.remove("result").unwrap_or(serde_json::Value::Null));
+        }
+
+        let mut field = |name: &str| fields.remove(name).and_then(|v| v.as_str().map(str::to_string));
+        let stack = field("error").unwrap_or_else(|| "Unknown JavaScript error".to_string());
+        let error = if field("phase").as_deref() == Some("compile") {
+            RuntimeError::compile("javascript", stack)
+        } else {
+            let exception_type = field("type").unwrap_or_else(|| "Error".to_string());
+            let message = field("message").unwrap_or_default();
+            RuntimeError::exception("javascript", &exception_type, &message, &stack)
+        };
+        let location = source_map::first_location(&error.message, &block.origin.file);
+        Err(error.with_location(location))
+    }
+}
+
+impl ForeignRuntime for JavaScriptRuntime {
+    fn language(&self) -> &str {
+        "javascript"
+    }
+
+    fn aliases(&self) -> &[&str] {
+        &["js"]
+    }
+
+    fn info(&self) -> RuntimeInfo {
+        let capabilities = Capabilities {
+            parallel: true,
+            ..Capabilities::default()
+        };
+        RuntimeInfo::probe("javascript", "node", capabilities)
+    }
+
+    /// Every call already runs in its own `node` process
+    fn fork(&self) -> Option<Box<dyn ForeignRuntime>> {
+        Some(Box::new(JavaScriptRuntime))
+    }
+
+    fn invoke(
+        &mut self,
+        block: &ForeignBlock,
+        args: &[serde_json::Value],
+        limits: &ResourceLimits,
+        _callbacks: &mut dyn Callbacks,
+    ) -> Result<serde_json::Value, RuntimeError> {
+        let origin = &block.origin;
+        let payload = serde_json::json!([block.name, block.param_names(), block.code, origin.file, origin.line, args]);
+
+        let mut command = Command::new("node");
+        command
+            .arg("-e")
+            .arg(LAUNCHER_SOURCE)
+            .arg(channel::RESULT_MARKER);
+
+        let outcome = process::run_framed(command, payload.to_string(), limits)
+            .map_err(|e| RuntimeError::new("javascript", format!("Failed to start node: {}", e)))?;
+
+        match outcome {
+            Outcome::TimedOut => Err(RuntimeError::timeout("javascript", &block.name, limits.timeout.unwrap_or_default())),
+            Outcome::Exited { status, stderr, .. } if limits.explains(&status, &stderr) => {
+                Err(RuntimeError::resource_limit("javascript", &block.name, limits))
+            }
+            Outcome::Exited { frame: Some(json), .. } => {
+                let response = serde_json::from_str(&json)
+                    .map_err(|e| RuntimeError::decode("javascript", format!("Failed to parse result: {}", e)))?;
+                Self::into_result(response, block)
+            }
+            Outcome::Exited { status, frame: None, .. } if !status.success() => {
+                Err(RuntimeError::non_zero_exit("javascript", &block.name, status, ""))
+            }
+            Outcome::Exited { status, frame: None, .. } => {
+                Err(RuntimeError::new("javascript", format!("Block exited with {} without producing a result", status)))
+            }
+        }
+    }
+}
@@ -2,296 +2,600 @@
 // Runtime Manager - coordinates foreign runtime execution
 
 use std::collections::HashMap;
-use std::process::Command;
+use std::process::ExitStatus;
+use std::sync::{mpsc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use crate::ast::{HybridType, TypedParam};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+
+use super::c::CRuntime;
+use super::foreign::{Callbacks, ForeignRuntime, StreamId, Validation};
+use super::info::RuntimeInfo;
+use super::javascript::JavaScriptRuntime;
+use super::process::ResourceLimits;
+use super::python::PythonRuntime;
+use super::rust::RustRuntime;
+use super::shell::ShellRuntime;
+use super::source_map::SourceLocation;
+
+/// Key of the JSON object a `bytes` value is sent as
+pub const BYTES_KEY: &str = "$bytes";
 
 /// Represents a value that can be passed to/from foreign runtimes
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum RuntimeValue {
     Int(i64),
     Float(f64),
     String(String),
     Bool(bool),
+    Bytes(Vec<u8>),
+    Array(Vec<RuntimeValue>),
+    Map(HashMap<String, RuntimeValue>),
     Null,
 }
 
-/// Error from foreign runtime execution
+/// A foreign block as declared in Hybrid source (`#lang type block name(...) { ... }`)
+#[derive(Debug, Clone)]
+pub struct ForeignBlock {
+    pub name: String,
+    pub language: String,
+    pub code: String,
+    pub parameters: Vec<TypedParam>,
+    pub return_types: Vec<HybridType>,
+    /// Per-block limits from `@timeout`/`@memory`/`@cpu` attributes
+    pub limits: ResourceLimits,
+    /// Crates from `@crate(...)` attributes (`#rust` only)
+    pub dependencies: Vec<CrateDependency>,
+    /// Where the body's first line is in the `.hyb` source
+    pub origin: SourceLocation,
+}
+
+/// A Cargo dependency declared with `@crate("name", "version", "feature,...")`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrateDependency {
+    pub name: String,
+    pub version: String,
+    pub features: Vec<String>,
+}
+
+impl ForeignBlock {
+    pub fn param_names(&self) -> Vec<String> {
+        self.parameters.iter().map(|p| p.name.clone()).collect()
+    }
+
+    /// The item type of a block declared to return `iter[T]`
+    pub fn item_type(&self) -> Option<&HybridType> {
+        match self.return_types.as_slice() {
+            [HybridType::Iter(item)] => Some(item),
+            _ => None,
+        }
+    }
+}
+
+/// An `iter[T]` block's stream, opened with `RuntimeManager::open_stream`
 #[derive(Debug)]
+pub struct ForeignStream {
+    language: String,
+    id: StreamId,
+}
+
+/// What kind of failure a `RuntimeError` reports, with the data an
+/// embedder needs to react to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeErrorKind {
+    /// No runtime is registered for the language, or its toolchain is missing
+    Unavailable,
+    /// The block did not compile; `diagnostics` is the compiler's output
+    /// (for Python, the `SyntaxError` traceback)
+    Compile { diagnostics: String },
+    /// The block raised an exception, threw, or panicked
+    Exception {
+        /// `ValueError`, `TypeError`, `panic`, ...
+        exception_type: String,
+        message: String,
+        /// The foreign traceback or stack, if the language provides one
+        traceback: String,
+    },
+    /// The process exited unsuccessfully (`code` is `None` if it was killed
+    /// by a signal)
+    NonZeroExit { code: Option<i32>, stderr: String },
+    /// The call was killed after exceeding its wall-clock timeout
+    Timeout { limit: Duration },
+    /// The process was killed for exceeding a memory or CPU limit
+    ResourceLimit { limits: ResourceLimits },
+    /// The block's result could not be decoded into its declared types
+    Decode,
+    /// Anything else: failing to start a process, a broken worker, ...
+    Failed,
+}
+
+/// Error from foreign runtime execution. The kind and location are boxed
+/// so that `Result<_, RuntimeError>` stays small on the success path.
+#[derive(Debug, Clone)]
 pub struct RuntimeError {
     pub language: String,
+    /// Human-readable description, as shown by `Display`
     pub message: String,
+    pub kind: Box<RuntimeErrorKind>,
+    /// The `.hyb` line the error points at, when it can be traced to one
+    pub location: Option<Box<SourceLocation>>,
+}
+
+impl RuntimeError {
+    /// A `RuntimeErrorKind::Failed` error
+    pub fn new(language: &str, message: impl Into<String>) -> Self {
+        Self::with_kind(language, message, RuntimeErrorKind::Failed)
+    }
+    
+    fn with_kind(language: &str, message: impl Into<String>, kind: RuntimeErrorKind) -> Self {
+        RuntimeError {
+            language: language.to_string(),
+            message: message.into(),
+            kind: Box::new(kind),
+            location: None,
+        }
+    }
+    
+    pub fn with_location(mut self, location: Option<SourceLocation>) -> Self {
+        self.location = location.map(Box::new);
+        self
+    }
+    
+    pub fn unavailable(language: &str, message: impl Into<String>) -> Self {
+        Self::with_kind(language, message, RuntimeErrorKind::Unavailable)
+    }
+    
+    pub fn compile(language: &str, diagnostics: impl Into<String>) -> Self {
+        let diagnostics = diagnostics.into();
+        Self::with_kind(language, diagnostics.clone(), RuntimeErrorKind::Compile { diagnostics })
+    }
+    
+    /// An exception raised by the block; the message is the traceback when
+    /// there is one, `Type: message` otherwise
+    pub fn exception(language: &str, exception_type: &str, message: &str, traceback: &str) -> Self {
+        let display = if traceback.trim().is_empty() {
+            format!("{}: {}", exception_type, message)
+        } else {
+            traceback.trim_end().to_string()
+        };
+        let kind = RuntimeErrorKind::Exception {
+            exception_type: exception_type.to_string(),
+            message: message.to_string(),
+            traceback: traceback.to_string(),
+        };
+        Self::with_kind(language, display, kind)
+    }
+    
+    pub fn decode(language: &str, message: impl Into<String>) -> Self {
+        Self::with_kind(language, message, RuntimeErrorKind::Decode)
+    }
+    
+    pub fn timeout(language: &str, block_name: &str, limit: Duration) -> Self {
+        let message = format!("Block '{}' timed out after {:?}", block_name, limit);
+        Self::with_kind(language, message, RuntimeErrorKind::Timeout { limit })
+    }
+    
+    pub fn resource_limit(language: &str, block_name: &str, limits: &ResourceLimits) -> Self {
+        let message = format!("Block '{}' was killed for exceeding its limits ({})", block_name, limits.describe_rlimits());
+        Self::with_kind(language, message, RuntimeErrorKind::ResourceLimit { limits: *limits })
+    }
+    
+    pub fn non_zero_exit(language: &str, block_name: &str, status: ExitStatus, stderr: &str) -> Self {
+        let mut message = match status.code() {
+            Some(code) => format!("Block '{}' exited with code {}", block_name, code),
+            None => format!("Block '{}' was terminated ({})", block_name, status),
+        };
+        let stderr = stderr.trim_end();
+        if !stderr.is_empty() {
+            message.push_str(":\n");
+            message.push_str(stderr);
+        }
+        let kind = RuntimeErrorKind::NonZeroExit { code: status.code(), stderr: stderr.to_string() };
+        Self::with_kind(language, message, kind)
+    }
 }
 
 impl std::fmt::Display for RuntimeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "[{}] {}", self.language, self.message)
+        match &self.location {
+            Some(location) => write!(f, "[{}] {}: {}", self.language, location, self.message),
+            None => write!(f, "[{}] {}", self.language, self.message),
+        }
     }
 }
 
-/// Manages foreign runtime processes
+impl std::error::Error for RuntimeError {}
+
+/// A registered runtime and its toolchain info, detected on first use
+struct Registered {
+    runtime: Box<dyn ForeignRuntime>,
+    info: OnceLock<RuntimeInfo>,
+}
+
+impl Registered {
+    fn info(&self) -> &RuntimeInfo {
+        self.info.get_or_init(|| self.runtime.info())
+    }
+}
+
+/// Prepare and run `block` on `runtime`. An `iter[T]` block is run to the
+/// end and its items collected into one array.
+fn run_block(
+    runtime: &mut dyn ForeignRuntime,
+    block: &ForeignBlock,
+    args: &[serde_json::Value],
+    limits: &ResourceLimits,
+    callbacks: &mut dyn Callbacks,
+) -> Result<serde_json::Value, RuntimeError> {
+    runtime.prepare(block)?;
+    if block.item_type().is_none() {
+        return runtime.invoke(block, args, limits, callbacks);
+    }
+
+    let stream = runtime.open_stream(block, args, limits, callbacks)?;
+    let mut items = Vec::new();
+    let outcome = loop {
+        match runtime.next_item(stream, callbacks) {
+            Ok(Some(item)) => items.push(item),
+            Ok(None) => break Ok(serde_json::Value::Array(items)),
+            Err(e) => break Err(e),
+        }
+    };
+    runtime.close_stream(stream);
+    outcome
+}
+
+/// A callback from a parallel call: block name, arguments, and where to
+/// send the answer
+type CallbackRequest = (String, Vec<serde_json::Value>, mpsc::Sender<Result<serde_json::Value, String>>);
+
+/// `Callbacks` for a call on another thread, forwarding each request to
+/// the thread that owns the real `Callbacks`
+struct RelayCallbacks {
+    requests: mpsc::Sender<CallbackRequest>,
+}
+
+impl Callbacks for RelayCallbacks {
+    fn call(&mut self, name: &str, args: Vec<serde_json::Value>) -> Result<serde_json::Value, String> {
+        let (reply, answer) = mpsc::channel();
+        self.requests
+            .send((name.to_string(), args, reply))
+            .map_err(|_| format!("Cannot call '{}': callbacks are no longer served", name))?;
+        answer
+            .recv()
+            .map_err(|_| format!("Cannot call '{}': callbacks are no longer served", name))?
+    }
+}
+
+/// Dispatches foreign blocks to the `ForeignRuntime` registered for their language
 pub struct RuntimeManager {
-    available_runtimes: HashMap<String, bool>,
+    runtimes: HashMap<String, Registered>,
+    /// Alternative `#lang` names -> registered language
+    aliases: HashMap<String, String>,
+    /// Limits used for every block that does not override them
+    default_limits: ResourceLimits,
 }
 
 impl RuntimeManager {
-    /// Create a new RuntimeManager, checking which runtimes are available
+    /// Create a RuntimeManager with the built-in runtimes
     pub fn new() -> Self {
-        let mut available = HashMap::new();
-        
-        // Check Python
-        available.insert("python".to_string(), Self::check_python());
-        
-        // Check Rust
-        available.insert("rust".to_string(), Self::check_rust());
-        
+        let mut manager = Self::empty();
+        manager.register(PythonRuntime::new());
+        manager.register(RustRuntime::new());
+        manager.register(JavaScriptRuntime::new());
+        manager.register(ShellRuntime::new());
+        manager.register(CRuntime::new());
+        manager
+    }
+    
+    /// Create a RuntimeManager with no runtimes registered
+    pub fn empty() -> Self {
         RuntimeManager {
-            available_runtimes: available,
+            runtimes: HashMap::new(),
+            aliases: HashMap::new(),
+            default_limits: ResourceLimits::from_env(),
+        }
+    }
+    
+    /// Register a runtime for `#<runtime.language()>` blocks, replacing (and
+    /// shutting down) any runtime previously registered for that language
+    pub fn register(&mut self, runtime: impl ForeignRuntime + 'static) {
+        let language = runtime.language().to_string();
+        for alias in runtime.aliases() {
+            self.aliases.insert(alias.to_string(), language.clone());
+        }
+        let registered = Registered {
+            runtime: Box::new(runtime),
+            info: OnceLock::new(),
+        };
+        if let Some(mut previous) = self.runtimes.insert(language, registered) {
+            previous.runtime.shutdown();
         }
     }
     
-    fn check_python() -> bool {
-        Command::new("python3")
-            .arg("--version")
-            .output()
-            .map(|o| o.status.success())
-            .unwrap_or(false)
+    /// The registered language a `#lang` name refers to (resolving aliases)
+    pub fn resolve<'a>(&'a self, lang: &'a str) -> &'a str {
+        self.aliases.get(lang).map(String::as_str).unwrap_or(lang)
+    }
+    
+    /// Names of all registered languages, sorted
+    pub fn languages(&self) -> Vec<String> {
+        let mut languages: Vec<String> = self.runtimes.keys().cloned().collect();
+        languages.sort();
+        languages
+    }
+    
+    /// Set the limits applied to blocks that don't declare their own
+    pub fn set_default_limits(&mut self, limits: ResourceLimits) {
+        self.default_limits = limits;
     }
     
-    fn check_rust() -> bool {
-        Command::new("rustc")
-            .arg("--version")
-            .output()
-            .map(|o| o.status.success())
-            .unwrap_or(false)
+    pub fn default_limits(&self) -> ResourceLimits {
+        self.default_limits
     }
     
-    /// Check if a runtime is available
+    /// Check if a runtime is registered and its toolchain is installed
     pub fn is_available(&self, lang: &str) -> bool {
-        self.available_runtimes.get(lang).copied().unwrap_or(false)
+        self.info(lang).is_some_and(RuntimeInfo::is_available)
+    }
+    
+    /// The toolchain and capabilities of the runtime for `lang`, if one is
+    /// registered. Detected the first time it is asked for, then cached.
+    pub fn info(&self, lang: &str) -> Option<&RuntimeInfo> {
+        self.runtimes.get(self.resolve(lang)).map(Registered::info)
     }
     
-    /// Execute a foreign block
+    /// Execute a foreign block; calls it makes back into native blocks go
+    /// to `callbacks`
     pub fn execute(
-        &self,
-        lang: &str,
-        _block_name: &str,
-        code: &str,
-        param_names: &[String],
+        &mut self,
+        block: &ForeignBlock,
         args: Vec<RuntimeValue>,
+        callbacks: &mut dyn Callbacks,
     ) -> Result<Vec<RuntimeValue>, RuntimeError> {
-        if !self.is_available(lang) {
-            return Err(RuntimeError {
-                language: lang.to_string(),
-                message: format!("Runtime '{}' is not available", lang),
-            });
-        }
-        
-        match lang {
-            "python" => self.execute_python(code, param_names, args),
-            "rust" => self.execute_rust(code, param_names, args),
-            _ => Err(RuntimeError {
-                language: lang.to_string(),
-                message: format!("Unsupported runtime: {}", lang),
-            }),
-        }
+        let result = self.execute_json(block, &Self::values_to_json(&args), callbacks)?;
+        Self::split_results(result, block)
     }
     
-    fn execute_python(&self, code: &str, param_names: &[String], args: Vec<RuntimeValue>) -> Result<Vec<RuntimeValue>, RuntimeError> {
-        // Build parameter assignment lines
-        let param_assignments: Vec<String> = param_names
-            .iter()
-            .enumerate()
-            .map(|(i, name)| format!("{} = args[{}]", name, i))
-            .collect();
-        let param_setup = param_assignments.join("\n");
-        
-        // Build the Python wrapper script with named parameters
-        let wrapper = format!(r#"
-import json
-import sys
+    /// Execute a foreign block on JSON arguments, returning its raw JSON
+    /// result (multiple return values still packed in one array). Pipelines
+    /// use this to hand one block's result straight to the next.
+    pub fn execute_json(
+        &mut self,
+        block: &ForeignBlock,
+        args: &[serde_json::Value],
+        callbacks: &mut dyn Callbacks,
+    ) -> Result<serde_json::Value, RuntimeError> {
+        let limits = block.limits.or(self.default_limits);
+        let runtime = &mut self.registered(&block.language)?.runtime;
+        run_block(runtime.as_mut(), block, args, &limits, callbacks)
+    }
+    
+    /// Start an `iter[T]` block; its items are then pulled one at a time
+    /// with `next_item`, and the stream released with `close_stream`
+    pub fn open_stream(
+        &mut self,
+        block: &ForeignBlock,
+        args: &[serde_json::Value],
+        callbacks: &mut dyn Callbacks,
+    ) -> Result<ForeignStream, RuntimeError> {
+        let limits = block.limits.or(self.default_limits);
+        let language = self.resolve(&block.language).to_string();
+        let runtime = &mut self.registered(&block.language)?.runtime;
+        runtime.prepare(block)?;
+        let id = runtime.open_stream(block, args, &limits, callbacks)?;
+        Ok(ForeignStream { language, id })
+    }
+    
+    /// The next item of `stream`, or `None` once it is exhausted
+    pub fn next_item(
+        &mut self,
+        stream: &ForeignStream,
+        callbacks: &mut dyn Callbacks,
+    ) -> Result<Option<serde_json::Value>, RuntimeError> {
+        self.registered(&stream.language)?.runtime.next_item(stream.id, callbacks)
+    }
+    
+    /// Release a stream, stopping the block if it hasn't finished
+    pub fn close_stream(&mut self, stream: ForeignStream) {
+        if let Some(registered) = self.runtimes.get_mut(&stream.language) {
+            registered.runtime.close_stream(stream.id);
+        }
+    }
 
-# Arguments passed from Hybrid
-args = json.loads(sys.argv[1])
+    
+    /// Run several foreign calls at once and return their results in order.
+    /// Each call gets its own thread and a forked runtime (see
+    /// `ForeignRuntime::fork`); calls to runtimes that can't fork share one
+    /// thread and this manager's instances. Native blocks the calls call
+    /// back into are served by `callbacks`, one at a time, on this thread.
+    pub fn execute_parallel(
+        &mut self,
+        calls: &[(ForeignBlock, Vec<serde_json::Value>)],
+        callbacks: &mut dyn Callbacks,
+    ) -> Vec<Result<serde_json::Value, RuntimeError>> {
+        let mut results: Vec<Option<Result<serde_json::Value, RuntimeError>>> = calls.iter().map(|_| None).collect();
+        let mut forked = Vec::new();
+        let mut shared = Vec::new();
+        for (index, (block, _)) in calls.iter().enumerate() {
+            match self.registered(&block.language) {
+                Ok(registered) => match registered.runtime.fork() {
+                    Some(runtime) => forked.push((index, runtime)),
+                    None => shared.push(index),
+                },
+                Err(e) => results[index] = Some(Err(e)),
+            }
+        }
 
-# Inject parameters as variables
-{param_setup}
+        let default_limits = self.default_limits;
+        let (requests, relayed) = mpsc::channel::<CallbackRequest>();
+        thread::scope(|scope| {
+            let mut handles = Vec::new();
+            for (index, mut runtime) in forked {
+                let (block, args) = &calls[index];
+                let mut relay = RelayCallbacks { requests: requests.clone() };
+                handles.push(scope.spawn(move || {
+                    let limits = block.limits.or(default_limits);
+                    let result = run_block(runtime.as_mut(), block, args, &limits, &mut relay);
+                    runtime.shutdown();
+                    vec![(index, result)]
+                }));
+            }
+            if !shared.is_empty() {
+                let mut relay = RelayCallbacks { requests: requests.clone() };
+                let manager = &mut *self;
+                handles.push(scope.spawn(move || {
+                    shared
+                        .into_iter()
+                        .map(|index| (index, manager.execute_json(&calls[index].0, &calls[index].1, &mut relay)))
+                        .collect()
+                }));
+            }
 
-# User's function body (execute and capture result)
-def __hybrid_fn():
-{code}
+            // Serve callbacks until every call has finished and dropped its relay
+            drop(requests);
+            for (name, args, reply) in relayed {
+                let _ = reply.send(callbacks.call(&name, args));
+            }
+            for handle in handles {
+                let finished = handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+                for (index, result) in finished {
+                    results[index] = Some(result);
+                }
+            }
+        });
 
-result = __hybrid_fn()
-print(json.dumps(result if result is not None else None))
-"#, param_setup = param_setup, code = Self::indent_code(code, "    "));
-        
-        let args_json = serde_json::to_string(&Self::values_to_json(&args))
-            .map_err(|e| RuntimeError {
-                language: "python".to_string(),
-                message: format!("Failed to serialize args: {}", e),
-            })?;
-        
-        let output = Command::new("python3")
-            .arg("-c")
-            .arg(&wrapper)
-            .arg(&args_json)
-            .output()
-            .map_err(|e| RuntimeError {
-                language: "python".to_string(),
-                message: format!("Failed to execute Python: {}", e),
-            })?;
-        
-        if !output.status.success() {
-            return Err(RuntimeError {
-                language: "python".to_string(),
-                message: String::from_utf8_lossy(&output.stderr).to_string(),
-            });
-        }
-        
-        let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
-        Self::parse_json_result(&stdout, "python")
+        results
+            .into_iter()
+            .zip(calls)
+            .map(|(result, (block, _))| {
+                result.unwrap_or_else(|| Err(RuntimeError::new(&block.language, "Parallel call produced no result")))
+            })
+            .collect()
     }
     
-    fn execute_rust(&self, code: &str, param_names: &[String], args: Vec<RuntimeValue>) -> Result<Vec<RuntimeValue>, RuntimeError> {
-        use std::fs;
-        use std::env::temp_dir;
-        
-        // Build parameter assignment lines
-        let param_assignments: Vec<String> = param_names
+    /// Pre-flight check of `blocks` (compiling them, without running
+    /// anything), spread over one thread per CPU. Returns every failure, in
+    /// block order. Blocks whose runtime is unavailable are skipped; calling
+    /// them reports that.
+    pub fn validate(&mut self, blocks: &[ForeignBlock]) -> Vec<RuntimeError> {
+        let mut jobs: Vec<(usize, Validation)> = blocks
             .iter()
             .enumerate()
-            .map(|(i, name)| {
-                format!("    let {} = &args[{}];", name, i)
+            .filter_map(|(index, block)| {
+                let registered = self.registered(&block.language).ok()?;
+                registered.runtime.validator(block).map(|job| (index, job))
             })
             .collect();
-        let param_setup = param_assignments.join("\n");
-        
-        // Build Rust wrapper
-        let wrapper = format!(r#"
-use std::env;
-
-fn main() {{
-    let args: Vec<String> = env::args().skip(1).collect();
-{param_setup}
-    
-    // User's function body
-    let result = (|| {{
-{code}
-    }})();
-    
-    // Output result as JSON
-    println!("{{}}", serde_json_lite(&result));
-}}
-
-fn serde_json_lite<T: std::fmt::Debug>(v: &T) -> String {{
-    format!("{{:?}}", v)
-}}
-"#, param_setup = param_setup, code = Self::indent_code(code, "        "));
-        
-        // Write to temp file
-        let temp_path = temp_dir().join("hybrid_rust_block.rs");
-        let binary_path = temp_dir().join("hybrid_rust_block");
-        
-        fs::write(&temp_path, &wrapper).map_err(|e| RuntimeError {
-            language: "rust".to_string(),
-            message: format!("Failed to write temp file: {}", e),
-        })?;
-        
-        // Compile
-        let compile_output = Command::new("rustc")
-            .arg(&temp_path)
-            .arg("-o")
-            .arg(&binary_path)
-            .output()
-            .map_err(|e| RuntimeError {
-                language: "rust".to_string(),
-                message: format!("Failed to run rustc: {}", e),
-            })?;
-        
-        if !compile_output.status.success() {
-            return Err(RuntimeError {
-                language: "rust".to_string(),
-                message: String::from_utf8_lossy(&compile_output.stderr).to_string(),
-            });
-        }
-        
-        // Execute with args
-        let arg_strings: Vec<String> = args.iter().map(|v| match v {
-            RuntimeValue::Int(n) => n.to_string(),
-            RuntimeValue::Float(n) => n.to_string(),
-            RuntimeValue::String(s) => s.clone(),
-            RuntimeValue::Bool(b) => b.to_string(),
-            RuntimeValue::Null => "null".to_string(),
-        }).collect();
-        
-        let run_output = Command::new(&binary_path)
-            .args(&arg_strings)
-            .output()
-            .map_err(|e| RuntimeError {
-                language: "rust".to_string(),
-                message: format!("Failed to run binary: {}", e),
-            })?;
-        
-        // Clean up temp files
-        let _ = fs::remove_file(&temp_path);
-        let _ = fs::remove_file(&binary_path);
-        
-        if !run_output.status.success() {
-            return Err(RuntimeError {
-                language: "rust".to_string(),
-                message: String::from_utf8_lossy(&run_output.stderr).to_string(),
-            });
-        }
-        
-        let stdout = String::from_utf8_lossy(&run_output.stdout).trim().to_string();
-        
-        // Parse simple output (Rust debug format)
-        Ok(vec![Self::parse_rust_output(&stdout)])
-    }
-    
-    fn parse_rust_output(output: &str) -> RuntimeValue {
-        // Simple parsing of Rust debug output
-        if output == "true" {
-            RuntimeValue::Bool(true)
-        } else if output == "false" {
-            RuntimeValue::Bool(false)
-        } else if let Ok(n) = output.parse::<i64>() {
-            RuntimeValue::Int(n)
-        } else if let Ok(n) = output.parse::<f64>() {
-            RuntimeValue::Float(n)
-        } else if output.starts_with('"') && output.ends_with('"') {
-            RuntimeValue::String(output[1..output.len()-1].to_string())
-        } else {
-            RuntimeValue::String(output.to_string())
+        jobs.reverse();
+
+        let workers = thread::available_parallelism().map_or(1, |n| n.get()).min(jobs.len());
+        let queue = Mutex::new(jobs);
+        let failures = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    loop {
+                        let Some((index, job)) = queue.lock().unwrap().pop() else {
+                            break;
+                        };
+                        if let Err(e) = job() {
+                            failures.lock().unwrap().push((index, e));
+                        }
+                    }
+                });
+            }
+        });
+
+        let mut failures = failures.into_inner().unwrap();
+        failures.sort_by_key(|(index, _)| *index);
+        failures.into_iter().map(|(_, e)| e).collect()
+    }
+    
+    /// Run a `#lang setup { ... }` block whose body starts at `origin`
+    pub fn setup(
+        &mut self,
+        lang: &str,
+        code: &str,
+        origin: &SourceLocation,
+        callbacks: &mut dyn Callbacks,
+    ) -> Result<(), RuntimeError> {
+        let limits = self.default_limits;
+        let registered = self.registered(lang)?;
+        registered.runtime.setup(code, origin, &limits, callbacks)
+    }
+    
+    /// The runtime for `lang`, if it is registered and available
+    fn registered(&mut self, lang: &str) -> Result<&mut Registered, RuntimeError> {
+        let resolved = self.resolve(lang).to_string();
+        let registered = self
+            .runtimes
+            .get_mut(&resolved)
+            .ok_or_else(|| RuntimeError::unavailable(lang, format!("Unsupported runtime: {}", lang)))?;
+        if !registered.info().is_available() {
+            return Err(RuntimeError::unavailable(lang, format!("Runtime '{}' is not available (run 'hybrid doctor' for details)", lang)));
         }
+        Ok(registered)
     }
     
-    fn indent_code(code: &str, indent: &str) -> String {
-        code.lines()
-            .map(|line| format!("{}{}", indent, line))
-            .collect::<Vec<_>>()
-            .join("\n")
+    /// Shut down every registered runtime (also done on drop)
+    pub fn shutdown(&mut self) {
+        for registered in self.runtimes.values_mut() {
+            registered.runtime.shutdown();
+        }
     }
     
     fn values_to_json(values: &[RuntimeValue]) -> Vec<serde_json::Value> {
-        values.iter().map(|v| match v {
+        values.iter().map(Self::value_to_json).collect()
+    }
+    
+    /// JSON form of a value, as sent to foreign blocks
+    pub fn value_to_json(value: &RuntimeValue) -> serde_json::Value {
+        match value {
             RuntimeValue::Int(n) => serde_json::json!(n),
             RuntimeValue::Float(n) => serde_json::json!(n),
             RuntimeValue::String(s) => serde_json::json!(s),
             RuntimeValue::Bool(b) => serde_json::json!(b),
+            RuntimeValue::Bytes(b) => Self::bytes_to_json(b),
+            RuntimeValue::Array(items) => serde_json::Value::Array(Self::values_to_json(items)),
+            RuntimeValue::Map(map) => serde_json::Value::Object(
+                map.iter()
+                    .map(|(k, v)| (k.clone(), Self::value_to_json(v)))
+                    .collect(),
+            ),
             RuntimeValue::Null => serde_json::Value::Null,
-        }).collect()
+        }
     }
     
-    fn parse_json_result(json_str: &str, lang: &str) -> Result<Vec<RuntimeValue>, RuntimeError> {
-        let value: serde_json::Value = serde_json::from_str(json_str)
-            .map_err(|e| RuntimeError {
-                language: lang.to_string(),
-                message: format!("Failed to parse result: {}", e),
-            })?;
+    /// Convert a block's JSON result. A block declaring several return types
+    /// (`(int, string) block`) returns them as one JSON array, which is split
+    /// into one value per declared type.
+    pub fn split_results(value: serde_json::Value, block: &ForeignBlock) -> Result<Vec<RuntimeValue>, RuntimeError> {
+        let expected = block.return_types.len();
+        if expected <= 1 {
+            return Ok(vec![Self::json_to_value(value)]);
+        }
         
-        Ok(vec![Self::json_to_value(value)])
+        match value {
+            serde_json::Value::Array(items) if items.len() == expected => {
+                Ok(items.into_iter().map(Self::json_to_value).collect())
+            }
+            other => Err(RuntimeError::decode(
+                &block.language,
+                format!(
+                    "Block '{}' declares {} return values but returned {}",
+                    block.name, expected, other
+                ),
+            )),
+        }
     }
     
-    fn json_to_value(v: serde_json::Value) -> RuntimeValue {
+    /// Value for JSON received from a foreign block
+    pub fn json_to_value(v: serde_json::Value) -> RuntimeValue {
         match v {
             serde_json::Value::Null => RuntimeValue::Null,
             serde_json::Value::Bool(b) => RuntimeValue::Bool(b),
@@ -305,7 +609,186 @@ fn serde_json_lite<T: std::fmt::Debug>(v: &T) -> String {{
                 }
             }
             serde_json::Value::String(s) => RuntimeValue::String(s),
-            _ => RuntimeValue::Null, // Arrays and objects not yet supported
+            serde_json::Value::Array(items) => {
+                RuntimeValue::Array(items.into_iter().map(Self::json_to_value).collect())
+            }
+            serde_json::Value::Object(map) => match Self::json_to_bytes(&map) {
+                Some(bytes) => RuntimeValue::Bytes(bytes),
+                None => RuntimeValue::Map(
+                    map.into_iter()
+                        .map(|(k, v)| (k, Self::json_to_value(v)))
+                        .collect(),
+                ),
+            },
+        }
+    }
+    
+    /// JSON form of `bytes` values: `{"$bytes": "<base64>"}`, which runtimes
+    /// map to their native byte strings (`bytes`, `Vec<u8>`, `Buffer`)
+    pub fn bytes_to_json(bytes: &[u8]) -> serde_json::Value {
+        serde_json::json!({ BYTES_KEY: BASE64.encode(bytes) })
+    }
+    
+    /// The bytes a `{"$bytes": "<base64>"}` object stands for
+    fn json_to_bytes(map: &serde_json::Map<String, serde_json::Value>) -> Option<Vec<u8>> {
+        match map.get(BYTES_KEY) {
+            Some(serde_json::Value::String(encoded)) if map.len() == 1 => BASE64.decode(encoded).ok(),
+            _ => None,
         }
     }
 }
+
+impl Default for RuntimeManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for RuntimeManager {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::foreign::NoCallbacks;
+
+    /// A block named `name` whose body starts on line 10 of `test.hyb`
+    fn block(language: &str, name: &str, code: &str, return_type: HybridType) -> ForeignBlock {
+        ForeignBlock {
+            name: name.to_string(),
+            language: language.to_string(),
+            code: code.to_string(),
+            parameters: Vec::new(),
+            return_types: vec![return_type],
+            limits: ResourceLimits::default(),
+            dependencies: Vec::new(),
+            origin: SourceLocation::new("test.hyb", 10),
+        }
+    }
+
+    fn run(block: &ForeignBlock) -> Result<serde_json::Value, RuntimeError> {
+        RuntimeManager::new().execute_json(block, &[], &mut NoCallbacks)
+    }
+
+    #[test]
+    fn unknown_language_is_unavailable() {
+        let error = run(&block("cobol", "legacy", "DISPLAY 'HI'.", HybridType::Void)).unwrap_err();
+        assert_eq!(*error.kind, RuntimeErrorKind::Unavailable);
+    }
+
+    #[test]
+    fn python_exception_keeps_type_and_location() {
+        let code = "\n    x = 1\n    raise ValueError('bad input')\n";
+        let error = run(&block("python", "fails", code, HybridType::Int)).unwrap_err();
+        let RuntimeErrorKind::Exception { exception_type, message, .. } = &*error.kind else {
+            panic!("expected an exception, got {:?}", error.kind);
+        };
+        assert_eq!(exception_type, "ValueError");
+        assert_eq!(message, "bad input");
+        // Line 10 holds the (empty) rest of the opening brace's line
+        assert_eq!(error.location.as_deref(), Some(&SourceLocation::new("test.hyb", 12)));
+    }
+
+    #[test]
+    fn slow_block_times_out() {
+        let mut slow = block("python", "slow", "\n    import time\n    time.sleep(10)\n", HybridType::Void);
+        slow.limits.timeout = Some(Duration::from_millis(300));
+        let error = run(&slow).unwrap_err();
+        assert_eq!(*error.kind, RuntimeErrorKind::Timeout { limit: Duration::from_millis(300) });
+    }
+
+    #[test]
+    fn shell_exit_code_is_non_zero_exit() {
+        let error = run(&block("shell", "exits", "echo oops >&2; exit 3", HybridType::Void)).unwrap_err();
+        assert_eq!(*error.kind, RuntimeErrorKind::NonZeroExit { code: Some(3), stderr: "oops".to_string() });
+    }
+
+    #[test]
+    fn missing_return_value_is_decode_error() {
+        let mut wrong = block("python", "wrong", "\n    return 1\n", HybridType::Int);
+        wrong.return_types.push(HybridType::String);
+        let error = RuntimeManager::new().execute(&wrong, Vec::new(), &mut NoCallbacks).unwrap_err();
+        assert_eq!(*error.kind, RuntimeErrorKind::Decode);
+    }
+
+    #[test]
+    fn rust_int_parameter_rejects_fractions() {
+        let mut identity = block("rust", "identity", "\n    x\n", HybridType::Int);
+        identity.parameters.push(TypedParam { name: "x".to_string(), param_type: HybridType::Int });
+        let mut manager = RuntimeManager::new();
+        assert_eq!(manager.execute_json(&identity, &[serde_json::json!(3.0)], &mut NoCallbacks).unwrap(), 3);
+        let error = manager.execute_json(&identity, &[serde_json::json!(2.5)], &mut NoCallbacks).unwrap_err();
+        assert!(error.message.contains("is not an int"), "{}", error);
+    }
+
+    #[test]
+    fn c_int_parameter_rejects_fractions() {
+        let mut identity = block("c", "identity", "\n    return x;\n", HybridType::Int);
+        identity.parameters.push(TypedParam { name: "x".to_string(), param_type: HybridType::Int });
+        let mut manager = RuntimeManager::new();
+        assert_eq!(manager.execute_json(&identity, &[serde_json::json!(3.0)], &mut NoCallbacks).unwrap(), 3);
+        let error = manager.execute_json(&identity, &[serde_json::json!(2.5)], &mut NoCallbacks).unwrap_err();
+        // The reason went to stderr, which is forwarded rather than kept
+        assert!(matches!(*error.kind, RuntimeErrorKind::NonZeroExit { code: Some(2), .. }), "{}", error);
+    }
+
+    #[test]
+    fn python_setup_state_is_per_worker() {
+        let mut manager = RuntimeManager::new();
+        let origin = SourceLocation::new("test.hyb", 1);
+        manager.setup("python", "\n    calls = 0\n", &origin, &mut NoCallbacks).unwrap();
+        let tick = block("python", "tick", "\n    global calls\n    calls += 1\n    return calls\n", HybridType::Int);
+        let mut limited = tick.clone();
+        limited.limits.memory_bytes = Some(256 << 20);
+        let mut call = |block: &ForeignBlock| manager.execute_json(block, &[], &mut NoCallbacks).unwrap();
+
+        assert_eq!(call(&tick), 1);
+        // A dedicated worker replays the setup and drops its own changes
+        assert_eq!(call(&limited), 1);
+        assert_eq!(call(&limited), 1);
+        assert_eq!(call(&tick), 2);
+
+        let results = manager.execute_parallel(&[(tick.clone(), Vec::new())], &mut NoCallbacks);
+        assert_eq!(results[0].as_ref().unwrap(), 1);
+        assert_eq!(manager.execute_json(&tick, &[], &mut NoCallbacks).unwrap(), 3);
+    }
+
+    #[test]
+    fn bytes_round_trip_as_base64() {
+        let bytes = RuntimeValue::Bytes(vec![0, 1, 0xff, b'h']);
+        let json = RuntimeManager::value_to_json(&bytes);
+        assert_eq!(json, serde_json::json!({ "$bytes": "AAH/aA==" }));
+        assert_eq!(RuntimeManager::json_to_value(json), bytes);
+
+        // Only a lone `$bytes` key holding valid base64 is bytes
+        let extra_key = serde_json::json!({ "$bytes": "AAH/aA==", "n": 1 });
+        assert!(matches!(RuntimeManager::json_to_value(extra_key), RuntimeValue::Map(_)));
+        let invalid = serde_json::json!({ "$bytes": "not base64!" });
+        assert!(matches!(RuntimeManager::json_to_value(invalid), RuntimeValue::Map(_)));
+    }
+
+    #[test]
+    fn python_bytes_round_trip() {
+        let mut reverse = block("python", "reverse", "\n    return data[::-1]\n", HybridType::Bytes);
+        reverse.parameters.push(TypedParam { name: "data".to_string(), param_type: HybridType::Bytes });
+        let args = [RuntimeManager::bytes_to_json(&[0, 1, 0xff])];
+        let result = RuntimeManager::new().execute_json(&reverse, &args, &mut NoCallbacks).unwrap();
+        assert_eq!(RuntimeManager::json_to_value(result), RuntimeValue::Bytes(vec![0xff, 1, 0]));
+    }
+
+    #[test]
+    fn validate_reports_compile_errors_with_locations() {
+        let blocks = [
+            block("python", "fine", "\n    return 1\n", HybridType::Int),
+            block("python", "broken", "\n    return (1\n", HybridType::Int),
+            block("cobol", "skipped", "DISPLAY 'HI'.", HybridType::Void),
+        ];
+        let errors = RuntimeManager::new().validate(&blocks);
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(matches!(*errors[0].kind, RuntimeErrorKind::Compile { .. }));
+        assert_eq!(errors[0].location.as_ref().map(|l| (l.file.as_str(), l.line)), Some(("test.hyb", 11)));
+    }
+}
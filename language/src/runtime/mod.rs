@@ -1,7 +1,19 @@
 // runtime/mod.rs
 // Polyglot Runtime System for Hybrid Language
 
+pub mod c;
+pub mod cache;
+pub mod channel;
+pub mod foreign;
+pub mod info;
+pub mod javascript;
 pub mod manager;
+pub mod process;
+pub mod python;
+pub mod rust;
+pub mod shell;
+pub mod source_map;
+pub mod workspace;
 
-// Note: scanner, python, rust modules kept for future expansion
+// Note: scanner module kept for future expansion
 
@@ -0,0 +1,477 @@
+// runtime/process.rs
+// Supervision of foreign child processes: timeouts and resource limits
+
+use std::io::{self, BufReader, Read, Write};
+use std::process::{Child, ChildStderr, Command, ExitStatus, Output, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::channel;
+
+/// Environment variable holding the default per-call timeout in seconds
+pub const TIMEOUT_ENV: &str = "HYBRID_TIMEOUT";
+
+/// Limits applied to a single foreign call.
+///
+/// `timeout` is a wall-clock deadline enforced by Hybrid; `memory_bytes`
+/// and `cpu_seconds` are applied to the child process as rlimits
+/// (`RLIMIT_AS` / `RLIMIT_CPU`) on Unix.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ResourceLimits {
+    pub timeout: Option<Duration>,
+    pub memory_bytes: Option<u64>,
+    pub cpu_seconds: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// Read the global default timeout from `HYBRID_TIMEOUT`
+    pub fn from_env() -> Self {
+        let timeout = std::env::var(TIMEOUT_ENV)
+            .ok()
+            .and_then(|v| v.trim().parse::<f64>().ok())
+            .filter(|secs| *secs > 0.0)
+            .map(Duration::from_secs_f64);
+        ResourceLimits { timeout, ..Default::default() }
+    }
+
+    /// Fill every limit not set here from `defaults`
+    pub fn or(self, defaults: ResourceLimits) -> Self {
+        ResourceLimits {
+            timeout: self.timeout.or(defaults.timeout),
+            memory_bytes: self.memory_bytes.or(defaults.memory_bytes),
+            cpu_seconds: self.cpu_seconds.or(defaults.cpu_seconds),
+        }
+    }
+
+    /// Whether any limit must be applied to the process itself
+    pub fn has_rlimits(&self) -> bool {
+        self.memory_bytes.is_some() || self.cpu_seconds.is_some()
+    }
+
+    /// Arrange for the rlimits to be applied to `command`'s child process
+    pub fn apply(&self, command: &mut Command) {
+        #[cfg(unix)]
+        if self.has_rlimits() {
+            use std::os::unix::process::CommandExt;
+
+            let limits = *self;
+            // SAFETY: setrlimit is async-signal-safe and touches no memory
+            // shared with the parent.
+            unsafe {
+                command.pre_exec(move || limits.set_rlimits());
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = command;
+    }
+
+    #[cfg(unix)]
+    fn set_rlimits(&self) -> io::Result<()> {
+        let set = |resource, soft: u64, hard: u64| {
+            let limit = libc::rlimit {
+                rlim_cur: soft as libc::rlim_t,
+                rlim_max: hard as libc::rlim_t,
+            };
+            // SAFETY: `limit` is a valid, initialised rlimit struct
+            if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        };
+        if let Some(bytes) = self.memory_bytes {
+            set(libc::RLIMIT_AS, bytes, bytes)?;
+        }
+        // A hard limit equal to the soft one gets SIGKILL rather than
+        // SIGXCPU, which would leave nothing to tell it from other kills
+        if let Some(secs) = self.cpu_seconds {
+            set(libc::RLIMIT_CPU, secs, secs + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Whether a process that ended with `status`, having written `stderr`,
+    /// was stopped by one of its rlimits: SIGXCPU for the CPU limit, or an
+    /// abort or kill following a failed allocation for the memory limit.
+    /// Other signals (e.g. a segfault) are the block's own doing.
+    pub fn explains(&self, status: &ExitStatus, stderr: &str) -> bool {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            match status.signal() {
+                Some(libc::SIGXCPU) => self.cpu_seconds.is_some(),
+                Some(libc::SIGABRT | libc::SIGKILL) => self.memory_bytes.is_some() && allocation_failed(stderr),
+                _ => false,
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (status, stderr);
+            false
+        }
+    }
+
+    /// Human-readable summary, e.g. "memory 256 MB, cpu 10s"
+    pub fn describe_rlimits(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(bytes) = self.memory_bytes {
+            parts.push(format!("memory {} MB", bytes / (1024 * 1024)));
+        }
+        if let Some(secs) = self.cpu_seconds {
+            parts.push(format!("cpu {}s", secs));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Messages runtimes print when an allocation fails, matched case-insensitively
+const ALLOCATION_FAILURES: &[&str] = &[
+    "memory allocation of",   // Rust's default alloc error handler
+    "out of memory",          // Node.js heap, glibc and most C code
+    "cannot allocate memory", // ENOMEM
+    "bad_alloc",              // C++
+    "memoryerror",            // Python
+];
+
+fn allocation_failed(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    ALLOCATION_FAILURES.iter().any(|signature| stderr.contains(signature))
+}
+
+/// Bytes at the end of a child's stderr kept for `ResourceLimits::explains`
+const STDERR_TAIL: usize = 4096;
+
+/// Forward a child's stderr to ours as it arrives, returning the last
+/// `STDERR_TAIL` bytes of it once it closes
+fn tee_stderr(mut stderr: ChildStderr) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut tail = Vec::new();
+        let mut buf = [0; 4096];
+        while let Ok(n) = stderr.read(&mut buf)
+            && n > 0
+        {
+            let _ = io::stderr().write_all(&buf[..n]);
+            tail.extend_from_slice(&buf[..n]);
+            if tail.len() > STDERR_TAIL {
+                tail.drain(..tail.len() - STDERR_TAIL);
+            }
+        }
+        String::from_utf8_lossy(&tail).into_owned()
+    })
+}
+
+/// How a supervised child process ended
+pub enum Outcome {
+    /// The process exited; `frame` is its framed result line, if any, and
+    /// `stderr` the end of what it wrote to stderr (which was forwarded too)
+    Exited { status: ExitStatus, frame: Option<String>, stderr: String },
+    /// The process was killed after exceeding its timeout
+    TimedOut,
+}
+
+/// Run `command` to completion with `input` on its stdin and its stdout
+/// forwarded through the result channel, killing it if it outlives
+/// `limits.timeout`.
+pub fn run_framed(mut command: Command, input: String, limits: &ResourceLimits) -> io::Result<Outcome> {
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    limits.apply(&mut command);
+
+    let deadline = limits.timeout.map(|limit| Instant::now() + limit);
+    let mut child = command.spawn()?;
+    feed(&mut child, input);
+    let stdout = child.stdout.take().ok_or_else(|| io::Error::other("stdout was not captured"))?;
+    let stderr = tee_stderr(child.stderr.take().ok_or_else(|| io::Error::other("stderr was not captured"))?);
+
+    // Read on a helper thread so the deadline can be enforced here
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stdout);
+        let frame = channel::forward_until_frame(&mut reader)
+            .and_then(|frame| channel::forward_rest(&mut reader).map(|_| frame));
+        let _ = tx.send(frame);
+    });
+
+    let frame = match wait_for(&rx, limits.timeout) {
+        Ok(frame) => frame,
+        Err(RecvTimeoutError::Timeout) => {
+            kill(&mut child);
+            return Ok(Outcome::TimedOut);
+        }
+        Err(RecvTimeoutError::Disconnected) => {
+            kill(&mut child);
+            return Err(io::Error::other("stdout reader stopped"));
+        }
+    };
+
+    // Closing stdout doesn't mean the child is done
+    let Some(status) = wait_until(&mut child, deadline)? else {
+        return Ok(Outcome::TimedOut);
+    };
+    let stderr = stderr.join().unwrap_or_default();
+    Ok(Outcome::Exited { status, frame: frame?, stderr })
+}
+
+/// A running child whose framed stdout lines are taken one at a time, for
+/// blocks that produce several results. Other output is forwarded as it
+/// arrives. The child is killed if dropped early.
+pub struct FramedChild {
+    child: Child,
+    frames: mpsc::Receiver<io::Result<Option<String>>>,
+    stderr: Option<thread::JoinHandle<String>>,
+}
+
+impl FramedChild {
+    /// Start `command` under `limits`' rlimits with `input` on its stdin
+    pub fn spawn(mut command: Command, input: String, limits: &ResourceLimits) -> io::Result<Self> {
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        limits.apply(&mut command);
+
+        let mut child = command.spawn()?;
+        feed(&mut child, input);
+        let stdout = child.stdout.take().ok_or_else(|| io::Error::other("stdout was not captured"))?;
+        let stderr = tee_stderr(child.stderr.take().ok_or_else(|| io::Error::other("stderr was not captured"))?);
+
+        // A rendezvous channel: the reader stops at each frame until it is
+        // taken, so the child can only run ahead by what fits in the pipe
+        // before it blocks on a full stdout
+        let (tx, frames) = mpsc::sync_channel(0);
+        thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            loop {
+                let frame = channel::forward_until_frame(&mut reader);
+                let done = !matches!(frame, Ok(Some(_)));
+                if tx.send(frame).is_err() || done {
+                    break;
+                }
+            }
+        });
+
+        Ok(FramedChild { child, frames, stderr: Some(stderr) })
+    }
+
+    /// The next frame, or `None` once stdout is closed. If none arrives
+    /// within `timeout` the child is killed and an `io::ErrorKind::TimedOut`
+    /// error returned.
+    pub fn next_frame(&mut self, timeout: Option<Duration>) -> io::Result<Option<String>> {
+        match wait_for(&self.frames, timeout) {
+            Ok(frame) => frame,
+            Err(RecvTimeoutError::Timeout) => {
+                kill(&mut self.child);
+                Err(io::Error::new(io::ErrorKind::TimedOut, "child timed out"))
+            }
+            // The reader stops after the last frame, or after an error it
+            // has already passed on
+            Err(RecvTimeoutError::Disconnected) => Ok(None),
+        }
+    }
+
+    /// Wait for the child to exit
+    pub fn wait(&mut self) -> io::Result<ExitStatus> {
+        self.child.wait()
+    }
+
+    /// The end of what the child wrote to stderr, once it has exited
+    pub fn stderr(&mut self) -> String {
+        self.stderr.take().and_then(|tail| tail.join().ok()).unwrap_or_default()
+    }
+}
+
+impl Drop for FramedChild {
+    fn drop(&mut self) {
+        if matches!(self.child.try_wait(), Ok(None)) {
+            kill(&mut self.child);
+        }
+    }
+}
+
+/// Run `command` to completion capturing its stdout and stderr, killing it
+/// if it outlives `limits.timeout`. Returns `None` on timeout.
+pub fn run_captured(command: Command, limits: &ResourceLimits) -> io::Result<Option<Output>> {
+    run_collecting(command, limits, false)
+}
+
+/// Like `run_captured`, but stdout is forwarded line by line as it arrives
+/// instead of being collected; the returned `Output::stdout` is empty.
+pub fn run_forwarding_stdout(command: Command, limits: &ResourceLimits) -> io::Result<Option<Output>> {
+    run_collecting(command, limits, true)
+}
+
+fn run_collecting(mut command: Command, limits: &ResourceLimits, forward_stdout: bool) -> io::Result<Option<Output>> {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    limits.apply(&mut command);
+
+    let deadline = limits.timeout.map(|limit| Instant::now() + limit);
+    let mut child = command.spawn()?;
+    let mut stdout = child.stdout.take().ok_or_else(|| io::Error::other("stdout was not captured"))?;
+    let mut stderr = child.stderr.take().ok_or_else(|| io::Error::other("stderr was not captured"))?;
+
+    // Drain both pipes concurrently so neither can fill up and block the child
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let errors = thread::spawn(move || {
+            let mut buf = Vec::new();
+            stderr.read_to_end(&mut buf).map(|_| buf)
+        });
+        let mut out = Vec::new();
+        let read = if forward_stdout {
+            channel::forward_rest(&mut BufReader::new(stdout))
+        } else {
+            stdout.read_to_end(&mut out).map(|_| ())
+        };
+        let result = read.and_then(|_| {
+            let err = errors.join().unwrap_or_else(|_| Err(io::Error::other("stderr reader panicked")))?;
+            Ok((out, err))
+        });
+        let _ = tx.send(result);
+    });
+
+    let (stdout, stderr) = match wait_for(&rx, limits.timeout) {
+        Ok(streams) => streams?,
+        Err(RecvTimeoutError::Timeout) => {
+            kill(&mut child);
+            return Ok(None);
+        }
+        Err(RecvTimeoutError::Disconnected) => {
+            kill(&mut child);
+            return Err(io::Error::other("output reader stopped"));
+        }
+    };
+
+    let Some(status) = wait_until(&mut child, deadline)? else {
+        return Ok(None);
+    };
+    Ok(Some(Output { status, stdout, stderr }))
+}
+
+/// Wait for the child to exit, killing it if it is still running at
+/// `deadline` (if any). Returns `None` if it was killed.
+fn wait_until(child: &mut Child, deadline: Option<Instant>) -> io::Result<Option<ExitStatus>> {
+    let Some(deadline) = deadline else {
+        return child.wait().map(Some);
+    };
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            kill(child);
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Write `input` to the child's stdin and close it. This happens on a
+/// helper thread, so a child that never reads its input can still be
+/// timed out; a child that exits without reading it is not an error here.
+fn feed(child: &mut Child, input: String) {
+    if let Some(mut stdin) = child.stdin.take() {
+        thread::spawn(move || {
+            let _ = stdin.write_all(input.as_bytes());
+        });
+    }
+}
+
+/// Wait for a message, giving up after `timeout` (if any). A sender that
+/// went away is reported as `Disconnected`, never as a timeout.
+pub fn wait_for<T>(rx: &mpsc::Receiver<T>, timeout: Option<Duration>) -> Result<T, RecvTimeoutError> {
+    match timeout {
+        Some(limit) => rx.recv_timeout(limit),
+        None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+    }
+}
+
+/// Kill a child process and reap it
+pub fn kill(child: &mut Child) {
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn sh(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script);
+        command
+    }
+
+    fn limits(timeout: Option<Duration>) -> ResourceLimits {
+        ResourceLimits { timeout, ..Default::default() }
+    }
+
+    #[test]
+    fn run_framed_reads_input_and_frame() {
+        let command = sh(r#"read args; printf '\036hybrid-result:%s\n' "$args""#);
+        match run_framed(command, "[1,2]\n".to_string(), &limits(None)).unwrap() {
+            Outcome::Exited { status, frame, .. } => {
+                assert!(status.success());
+                assert_eq!(frame.as_deref(), Some("[1,2]"));
+            }
+            Outcome::TimedOut => panic!("unexpected timeout"),
+        }
+    }
+
+    #[test]
+    fn run_framed_kills_on_timeout() {
+        let started = Instant::now();
+        let outcome = run_framed(sh("sleep 10"), String::new(), &limits(Some(Duration::from_millis(200)))).unwrap();
+        assert!(matches!(outcome, Outcome::TimedOut));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn run_framed_times_out_after_stdout_closes() {
+        let started = Instant::now();
+        let command = sh(r#"printf '\036hybrid-result:1\n'; exec >&-; sleep 10"#);
+        let outcome = run_framed(command, String::new(), &limits(Some(Duration::from_millis(300)))).unwrap();
+        assert!(matches!(outcome, Outcome::TimedOut));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn framed_child_times_out_between_frames() {
+        let command = sh(r#"printf '\036hybrid-result:1\n'; sleep 10"#);
+        let mut child = FramedChild::spawn(command, String::new(), &limits(None)).unwrap();
+        let timeout = Some(Duration::from_millis(200));
+        assert_eq!(child.next_frame(timeout).unwrap().as_deref(), Some("1"));
+        let error = child.next_frame(timeout).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn wait_for_tells_disconnect_from_timeout() {
+        let (tx, rx) = mpsc::channel::<()>();
+        assert_eq!(wait_for(&rx, Some(Duration::from_millis(10))), Err(RecvTimeoutError::Timeout));
+        drop(tx);
+        assert_eq!(wait_for(&rx, Some(Duration::from_secs(5))), Err(RecvTimeoutError::Disconnected));
+        assert_eq!(wait_for(&rx, None), Err(RecvTimeoutError::Disconnected));
+    }
+
+    #[test]
+    fn explains_only_limit_signals() {
+        let killed_by = ExitStatus::from_raw;
+        let both = ResourceLimits {
+            memory_bytes: Some(64 << 20),
+            cpu_seconds: Some(1),
+            ..Default::default()
+        };
+        assert!(both.explains(&killed_by(libc::SIGXCPU), ""));
+        assert!(both.explains(&killed_by(libc::SIGABRT), "memory allocation of 1024 bytes failed"));
+        assert!(!both.explains(&killed_by(libc::SIGABRT), "assertion failed"));
+        assert!(!both.explains(&killed_by(libc::SIGSEGV), "out of memory"));
+        assert!(!limits(None).explains(&killed_by(libc::SIGXCPU), ""));
+        assert!(!both.explains(&ExitStatus::from_raw(1 << 8), "out of memory"));
+    }
+}
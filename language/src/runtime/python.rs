@@ -0,0 +1,867 @@
+// runtime/python.rs
+// Persistent Python worker process for #python blocks
+
+use std::collections::HashMap;
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::channel;
+use super::foreign::{Callbacks, ForeignRuntime, NoCallbacks, StreamId, Validation};
+use super::info::{self, Capabilities, RuntimeInfo};
+use super::manager::{ForeignBlock, RuntimeError};
+use super::process::{self, ResourceLimits};
+use super::source_map::{self, SourceLocation};
+
+/// Environment variable naming the Python interpreter to use
+pub const PYTHON_ENV: &str = "HYBRID_PYTHON";
+
+/// Environment variable naming a virtualenv whose interpreter to use
+pub const VENV_ENV: &str = "HYBRID_VENV";
+
+/// How long a worker gets to exit on its own after stdin is closed
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(500);
+
+/// The worker shim: reads one JSON request per line from stdin and writes
+/// one framed JSON response per line to stdout (see `channel`). The frame
+/// marker is passed as `sys.argv[1]`. User `print()` output shares stdout and
+/// is forwarded by the manager. The shim moves the request pipe to a private
+/// descriptor and points fd 0 at the null device, so blocks that read stdin
+/// (`input()`, `sys.stdin`) see end of file instead of the protocol.
+///
+/// Requests:
+///   {"op": "setup", "code": ..., "file": ..., "line": ...}
+///   {"op": "define", "name": ..., "params": [...], "code": ..., "file": ..., "line": ...}
+///   {"op": "call", "name": ..., "args": [...]}
+///   {"op": "iter", "name": ..., "args": [...]}   -> result is a stream id
+///   {"op": "next", "stream": id}                -> result is the next item,
+///                                                  or {"ok": true, "done": true}
+///   {"op": "close", "stream": id}
+///
+/// While a request runs, `hybrid.call("name", *args)` in block code calls a
+/// native Hybrid block: the worker writes a framed
+/// `{"callback": name, "args": [...]}` line and reads the reply
+/// (`{"ok": true, "result": ...}` or `{"ok": false, "error": ...}`) from
+/// stdin before carrying on, so callbacks nest inside the request. The
+/// called native block cannot itself call a foreign block (the runtime
+/// manager is busy with this request); that fails with
+/// "Foreign blocks cannot be called from within a callback", raised as
+/// `hybrid.Error`.
+///
+/// `bytes` values travel as `{"$bytes": "<base64>"}` and are `bytes` in
+/// Python; `bytearray` and `memoryview` results are sent as bytes too.
+///
+/// Blocks defined after a `setup` share its module namespace as their
+/// globals; before any setup each block gets a namespace of its own.
+/// Code is compiled under the `.hyb` file name and padded so its line
+/// numbers are the ones in that file, which is what tracebacks show.
+/// Responses:
+///   {"ok": true, "result": ...} or
+///   {"ok": false, "error": "<traceback>", "type": "ValueError", "message": ...}
+const WORKER_SOURCE: &str = r#"
+import base64
+import itertools
+import json
+import keyword
+import os
+import sys
+import textwrap
+import traceback
+
+_MARKER = sys.argv[1]
+_protocol = sys.stdout
+_requests = os.fdopen(os.dup(0), "r")
+_null = os.open(os.devnull, os.O_RDONLY)
+os.dup2(_null, 0)
+os.close(_null)
+
+_functions = {}
+_shared = None
+# Iterators of iter[T] blocks, advanced one "next" request at a time
+_streams = {}
+_stream_ids = itertools.count(1)
+
+def _decode(obj):
+    if len(obj) == 1 and isinstance(obj.get("$bytes"), str):
+        return base64.b64decode(obj["$bytes"])
+    return obj
+
+def _encode(value):
+    if isinstance(value, (bytes, bytearray, memoryview)):
+        return {"$bytes": base64.b64encode(value).decode("ascii")}
+    raise TypeError("Object of type " + type(value).__name__ + " is not JSON serializable")
+
+def _dumps(value):
+    try:
+        return json.dumps(value, default=_encode, allow_nan=False)
+    except ValueError as error:
+        # NaN and infinities have no JSON form
+        raise TypeError("Value cannot be sent to Hybrid: " + str(error)) from None
+
+def _loads(text):
+    return json.loads(text, object_hook=_decode)
+
+class HybridError(Exception):
+    """A native Hybrid block called through hybrid.call failed"""
+
+class _Hybrid:
+    Error = HybridError
+
+    def call(self, name, *args):
+        sys.stdout.flush()
+        _protocol.write(_MARKER + _dumps({"callback": name, "args": list(args)}) + "\n")
+        _protocol.flush()
+        line = _requests.readline()
+        if not line:
+            raise HybridError("Hybrid closed the connection")
+        reply = _loads(line)
+        if not reply.get("ok"):
+            raise HybridError(reply.get("error"))
+        return reply.get("result")
+
+hybrid = _Hybrid()
+
+def _setup(code, file, line):
+    global _shared
+    if _shared is None:
+        _shared = {"__name__": "__hybrid__", "hybrid": hybrid}
+    source = "\n" * max(line - 1, 0) + textwrap.dedent(code)
+    exec(compile(source, file, "exec"), _shared)
+
+def _define(name, params, code, file, line):
+    body = textwrap.indent(textwrap.dedent(code), "    ") or "    pass"
+    fname = name if name.isidentifier() and not keyword.iskeyword(name) else "__hybrid_fn"
+    # The def line sits just above the body's first line in the .hyb file
+    source = "\n" * max(line - 2, 0) + "def " + fname + "(" + ", ".join(params) + "):\n" + body + "\n"
+    namespace = _shared if _shared is not None else {"hybrid": hybrid}
+    scratch = {}
+    exec(compile(source, file, "exec"), namespace, scratch)
+    _functions[name] = scratch[fname]
+
+def _error_response():
+    # Drop the worker's own frames (including hybrid.call); only the
+    # block's code is of interest
+    kind, error, tb = sys.exc_info()
+    frames = [f for f in traceback.extract_tb(tb) if f.filename != "<string>"]
+    text = "Traceback (most recent call last):\n" + "".join(traceback.format_list(frames)) if frames else ""
+    return {
+        "ok": False,
+        "error": text + "".join(traceback.format_exception_only(kind, error)),
+        "type": kind.__name__,
+        "message": str(error),
+    }
+
+def _handle(request):
+    op = request.get("op")
+    if op == "setup":
+        _setup(request["code"], request["file"], request["line"])
+        return {"ok": True}
+    if op == "define":
+        _define(request["name"], request["params"], request["code"], request["file"], request["line"])
+        return {"ok": True}
+    if op == "call":
+        result = _functions[request["name"]](*request["args"])
+        return {"ok": True, "result": result}
+    if op == "iter":
+        stream = next(_stream_ids)
+        _streams[stream] = iter(_functions[request["name"]](*request["args"]))
+        return {"ok": True, "result": stream}
+    if op == "next":
+        try:
+            return {"ok": True, "result": next(_streams[request["stream"]])}
+        except StopIteration:
+            del _streams[request["stream"]]
+            return {"ok": True, "done": True}
+    if op == "close":
+        stream = _streams.pop(request["stream"], None)
+        if hasattr(stream, "close"):
+            stream.close()
+        return {"ok": True}
+    return {"ok": False, "error": "unknown op: " + repr(op)}
+
+for _line in _requests:
+    try:
+        _response = _dumps(_handle(_loads(_line)))
+    except BaseException:
+        _response = json.dumps(_error_response())
+    sys.stdout.flush()
+    _protocol.write(_MARKER + _response + "\n")
+    _protocol.flush()
+"#;
+
+/// The Python interpreter `#python` blocks run on, and where that choice
+/// came from (for `hybrid doctor`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PythonInterpreter {
+    pub program: PathBuf,
+    pub source: String,
+}
+
+impl PythonInterpreter {
+    /// Resolve the interpreter from `$HYBRID_PYTHON`, then `$HYBRID_VENV`,
+    /// then an activated `$VIRTUAL_ENV`, falling back to `python3` on `PATH`
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var_os(name).filter(|v| !v.is_empty());
+
+        if let Some(python) = var(PYTHON_ENV) {
+            return Self::from_path(Path::new(&python), PYTHON_ENV);
+        }
+        if let Some(venv) = var(VENV_ENV) {
+            return Self::in_venv(Path::new(&venv), VENV_ENV);
+        }
+        if let Some(venv) = var("VIRTUAL_ENV") {
+            return Self::in_venv(Path::new(&venv), "VIRTUAL_ENV");
+        }
+        PythonInterpreter {
+            program: PathBuf::from("python3"),
+            source: "PATH".to_string(),
+        }
+    }
+
+    /// An interpreter given explicitly; a directory is taken to be a virtualenv
+    pub fn from_path(path: &Path, source: &str) -> Self {
+        if path.is_dir() {
+            return Self::in_venv(path, source);
+        }
+        PythonInterpreter {
+            program: path.to_path_buf(),
+            source: source.to_string(),
+        }
+    }
+
+    fn in_venv(venv: &Path, source: &str) -> Self {
+        let program = if cfg!(windows) {
+            venv.join("Scripts").join("python.exe")
+        } else {
+            venv.join("bin").join("python")
+        };
+        PythonInterpreter {
+            program,
+            source: source.to_string(),
+        }
+    }
+
+    /// `--version` output (e.g. "Python 3.11.7"), if the interpreter runs;
+    /// queried once per process
+    pub fn version(&self) -> Option<String> {
+        info::version(&self.program)
+    }
+}
+
+/// Runs `#python` blocks on a shared `PythonWorker`, started lazily on the
+/// first call and restarted if it dies.
+///
+/// Blocks with `@memory` / `@cpu` limits, and calls in a `parallel` block
+/// (through `fork`), run on a worker of their own into which the setup
+/// blocks are replayed. They start from freshly set-up state, and whatever
+/// they change in it (`global` counters, caches) is thrown away with that
+/// worker: the shared namespace never sees it.
+pub struct PythonRuntime {
+    interpreter: PythonInterpreter,
+    worker: Option<PythonWorker>,
+    /// `#python setup` blocks run so far, replayed on every new worker
+    setup_blocks: Vec<(String, SourceLocation)>,
+    /// Open `iter[T]` streams
+    streams: HashMap<StreamId, PythonStream>,
+    next_stream: StreamId,
+}
+
+/// A generator (or other iterator) held by a worker for an `iter[T]` block
+struct PythonStream {
+    /// The iterator's id in the worker
+    id: u64,
+    block: ForeignBlock,
+    limits: ResourceLimits,
+    /// The stream's own worker, for blocks with rlimits; `None` for the shared one
+    dedicated: Option<PythonWorker>,
+}
+
+impl PythonRuntime {
+    /// A runtime on the interpreter configured in the environment
+    pub fn new() -> Self {
+        Self::with_interpreter(PythonInterpreter::from_env())
+    }
+
+    pub fn with_interpreter(interpreter: PythonInterpreter) -> Self {
+        PythonRuntime {
+            interpreter,
+            worker: None,
+            setup_blocks: Vec::new(),
+            streams: HashMap::new(),
+            next_stream: 0,
+        }
+    }
+
+    pub fn interpreter(&self) -> &PythonInterpreter {
+        &self.interpreter
+    }
+
+    /// Get the running worker, (re)starting it if needed
+    fn worker(&mut self, timeout: Option<Duration>, callbacks: &mut dyn Callbacks) -> Result<&mut PythonWorker, RuntimeError> {
+        if !self.worker.as_mut().is_some_and(|w| w.is_alive()) {
+            // Iterators on the old worker died with it
+            self.streams.retain(|_, stream| stream.dedicated.is_some());
+            let limits = ResourceLimits { timeout, ..Default::default() };
+            self.worker = Some(self.spawn_worker(&limits, callbacks)?);
+        }
+
+        self.worker.as_mut().ok_or_else(|| RuntimeError::new("python", "Python worker unavailable"))
+    }
+
+    /// Start a worker under `limits` and replay the setup blocks into it
+    fn spawn_worker(&self, limits: &ResourceLimits, callbacks: &mut dyn Callbacks) -> Result<PythonWorker, RuntimeError> {
+        let mut worker = PythonWorker::spawn(&self.interpreter.program, limits)
+            .map_err(|e| {
+                let program = self.interpreter.program.display();
+                RuntimeError::new("python", format!("Failed to start Python worker ({}): {}", program, e))
+            })?;
+
+        for (code, origin) in &self.setup_blocks {
+            match worker.setup(code, origin, limits.timeout, callbacks) {
+                Ok(Ok(())) => {}
+                Ok(Err(exception)) => {
+                    let message = format!("Setup block failed on worker restart:\n{}", exception.traceback.trim_end());
+                    return Err(RuntimeError::new("python", message));
+                }
+                Err(e) => return Err(RuntimeError::new("python", format!("Python worker failed during setup: {}", e))),
+            }
+        }
+        Ok(worker)
+    }
+
+    /// Forget the shared worker if it has died (e.g. after a timeout), so
+    /// the next call starts a fresh one
+    fn drop_dead_worker(&mut self) {
+        if !self.worker.as_mut().is_some_and(|w| w.is_alive()) {
+            self.worker = None;
+            self.streams.retain(|_, stream| stream.dedicated.is_some());
+        }
+    }
+
+    /// Turn the outcome of a worker request made for `block` into its result
+    /// or `RuntimeError`
+    fn outcome<T>(
+        worker: &mut PythonWorker,
+        outcome: io::Result<Result<T, PythonException>>,
+        block: &ForeignBlock,
+        limits: &ResourceLimits,
+    ) -> Result<T, RuntimeError> {
+        match outcome {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(exception)) if limits.memory_bytes.is_some() && exception.exception_type == "MemoryError" => {
+                Err(RuntimeError::resource_limit("python", &block.name, limits))
+            }
+            Ok(Err(exception)) => Err(exception.into_error(&block.origin)),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                Err(RuntimeError::timeout("python", &block.name, limits.timeout.unwrap_or_default()))
+            }
+            Err(e) => {
+                // Only a worker that exited has a status to explain; a live
+                // one broke the protocol (e.g. sent an undecodable frame)
+                // and is killed so the next call starts a fresh one
+                let status = if e.kind() == io::ErrorKind::UnexpectedEof { worker.exit_status() } else { None };
+                worker.kill();
+                // The worker's stderr is not captured, but a failed allocation
+                // surfaces as a MemoryError above rather than a signal
+                if status.is_some_and(|status| limits.explains(&status, "")) {
+                    Err(RuntimeError::resource_limit("python", &block.name, limits))
+                } else {
+                    Err(RuntimeError::new("python", format!("Python worker failed: {}", e)))
+                }
+            }
+        }
+    }
+}
+
+impl ForeignRuntime for PythonRuntime {
+    fn language(&self) -> &str {
+        "python"
+    }
+
+    fn info(&self) -> RuntimeInfo {
+        let capabilities = Capabilities {
+            setup: true,
+            callbacks: true,
+            streaming: true,
+            validation: true,
+            parallel: true,
+        };
+        RuntimeInfo::probe("python", &self.interpreter.program, capabilities)
+    }
+
+    fn invoke(
+        &mut self,
+        block: &ForeignBlock,
+        args: &[serde_json::Value],
+        limits: &ResourceLimits,
+        callbacks: &mut dyn Callbacks,
+    ) -> Result<serde_json::Value, RuntimeError> {
+        // rlimits apply to a whole process, so limited blocks get a worker of their own
+        let mut dedicated = None;
+        let worker = if limits.has_rlimits() {
+            dedicated.insert(self.spawn_worker(limits, callbacks)?)
+        } else {
+            self.worker(limits.timeout, callbacks)?
+        };
+
+        let outcome = worker
+            .ensure_defined(&block.name, &block.param_names(), &block.code, &block.origin, limits.timeout)
+            .and_then(|defined| match defined {
+                Ok(()) => worker.call(&block.name, args, limits.timeout, callbacks),
+                Err(exception) => Ok(Err(exception)),
+            });
+        let result = Self::outcome(worker, outcome, block, limits);
+
+        // A worker that timed out or died is gone; the next call starts a fresh one
+        if result.is_err() && dedicated.is_none() {
+            self.drop_dead_worker();
+        }
+        result
+    }
+
+    fn open_stream(
+        &mut self,
+        block: &ForeignBlock,
+        args: &[serde_json::Value],
+        limits: &ResourceLimits,
+        callbacks: &mut dyn Callbacks,
+    ) -> Result<StreamId, RuntimeError> {
+        let mut dedicated = None;
+        let worker = if limits.has_rlimits() {
+            dedicated.insert(self.spawn_worker(limits, callbacks)?)
+        } else {
+            self.worker(limits.timeout, callbacks)?
+        };
+
+        let outcome = worker
+            .ensure_defined(&block.name, &block.param_names(), &block.code, &block.origin, limits.timeout)
+            .and_then(|defined| match defined {
+                Ok(()) => worker.open_stream(&block.name, args, limits.timeout, callbacks),
+                Err(exception) => Ok(Err(exception)),
+            });
+        let id = match Self::outcome(worker, outcome, block, limits) {
+            Ok(id) => id,
+            Err(e) => {
+                if dedicated.is_none() {
+                    self.drop_dead_worker();
+                }
+                return Err(e);
+            }
+        };
+
+        self.next_stream += 1;
+        let stream = PythonStream {
+            id,
+            block: block.clone(),
+            limits: *limits,
+            dedicated,
+        };
+        self.streams.insert(self.next_stream, stream);
+        Ok(self.next_stream)
+    }
+
+    fn next_item(&mut self, stream: StreamId, callbacks: &mut dyn Callbacks) -> Result<Option<serde_json::Value>, RuntimeError> {
+        let Some(PythonStream { id, block, limits, dedicated }) = self.streams.get_mut(&stream) else {
+            return Err(RuntimeError::new("python", "Stream is not open (its Python worker may have been restarted)"));
+        };
+        let worker = match dedicated.as_mut() {
+            Some(worker) => worker,
+            None => self
+                .worker
+                .as_mut()
+                .ok_or_else(|| RuntimeError::new("python", "Python worker unavailable"))?,
+        };
+
+        let outcome = worker.next_item(*id, limits.timeout, callbacks);
+        let result = Self::outcome(worker, outcome, block, limits);
+        if result.is_err() && dedicated.is_none() {
+            self.drop_dead_worker();
+        }
+        result
+    }
+
+    fn close_stream(&mut self, stream: StreamId) {
+        let Some(stream) = self.streams.remove(&stream) else {
+            return;
+        };
+        // A dedicated worker is simply dropped; the shared one is told to
+        // close the iterator, running a generator's `finally` blocks
+        if stream.dedicated.is_none()
+            && let Some(worker) = self.worker.as_mut()
+        {
+            let _ = worker.close_stream(stream.id, stream.limits.timeout);
+        }
+    }
+
+    /// A runtime on the same interpreter and setup blocks; its worker is
+    /// started (and set up) on its first call
+    fn fork(&self) -> Option<Box<dyn ForeignRuntime>> {
+        let mut runtime = PythonRuntime::with_interpreter(self.interpreter.clone());
+        runtime.setup_blocks = self.setup_blocks.clone();
+        Some(Box::new(runtime))
+    }
+
+    /// Byte-compile the block's function on a throwaway worker, without
+    /// running it or any setup blocks
+    fn validator(&self, block: &ForeignBlock) -> Option<Validation> {
+        let program = self.interpreter.program.clone();
+        let block = block.clone();
+        Some(Box::new(move || {
+            let mut worker = PythonWorker::spawn(&program, &ResourceLimits::default()).map_err(|e| {
+                RuntimeError::new("python", format!("Failed to start Python worker ({}): {}", program.display(), e))
+            })?;
+            match worker.ensure_defined(&block.name, &block.param_names(), &block.code, &block.origin, None) {
+                Ok(Ok(())) => Ok(()),
+                Ok(Err(exception)) => Err(exception.into_error(&block.origin)),
+                Err(e) => Err(RuntimeError::new("python", format!("Python worker failed: {}", e))),
+            }
+        }))
+    }
+
+    fn setup(
+        &mut self,
+        code: &str,
+        origin: &SourceLocation,
+        limits: &ResourceLimits,
+        callbacks: &mut dyn Callbacks,
+    ) -> Result<(), RuntimeError> {
+        let worker = self.worker(limits.timeout, callbacks)?;
+        let error = match worker.setup(code, origin, limits.timeout, callbacks) {
+            Ok(Ok(())) => {
+                self.setup_blocks.push((code.to_string(), origin.clone()));
+                return Ok(());
+            }
+            Ok(Err(exception)) => exception.into_error(origin),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                RuntimeError::timeout("python", "setup", limits.timeout.unwrap_or_default())
+            }
+            Err(e) => {
+                worker.kill();
+                RuntimeError::new("python", format!("Python worker failed: {}", e))
+            }
+        };
+
+        if !self.worker.as_mut().is_some_and(|w| w.is_alive()) {
+            self.worker = None;
+        }
+        Err(error)
+    }
+
+    fn shutdown(&mut self) {
+        self.streams.clear();
+        self.worker = None;
+    }
+}
+
+/// An exception raised in the worker, as reported in its error response
+#[derive(Debug, Clone)]
+pub struct PythonException {
+    pub exception_type: String,
+    pub message: String,
+    pub traceback: String,
+}
+
+impl PythonException {
+    /// The `RuntimeError` for this exception in code starting at `origin`;
+    /// syntax errors are reported as compile failures
+    fn into_error(self, origin: &SourceLocation) -> RuntimeError {
+        let location = source_map::traceback_location(&self.traceback, &origin.file);
+        let error = match self.exception_type.as_str() {
+            "SyntaxError" | "IndentationError" | "TabError" => RuntimeError::compile("python", self.traceback.trim_end()),
+            _ => RuntimeError::exception("python", &self.exception_type, &self.message, &self.traceback),
+        };
+        error.with_location(location)
+    }
+}
+
+/// A long-lived `python3` process that caches each block's compiled
+/// function by name and serves repeated calls without re-spawning.
+pub struct PythonWorker {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    /// Response frames read off the worker's stdout by a helper thread
+    /// (`Ok(None)` once stdout closes)
+    frames: Receiver<io::Result<Option<String>>>,
+    /// Block name -> source (and where it came from) the worker currently
+    /// has compiled for it
+    defined: HashMap<String, (String, SourceLocation)>,
+}
+
+impl PythonWorker {
+    /// Start a new worker process on `program`, with `limits`' rlimits applied to it
+    pub fn spawn(program: &Path, limits: &ResourceLimits) -> io::Result<Self> {
+        let mut command = Command::new(program);
+        command
+            .arg("-u")
+            .arg("-c")
+            .arg(WORKER_SOURCE)
+            .arg(channel::RESULT_MARKER)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+        limits.apply(&mut command);
+        let mut child = command.spawn()?;
+
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take().ok_or_else(|| {
+            io::Error::other("worker stdout was not captured")
+        })?;
+
+        // Forward user output and collect response frames off-thread, so a
+        // call can be abandoned when it exceeds its timeout
+        let (tx, frames) = mpsc::channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            loop {
+                let frame = channel::forward_until_frame(&mut reader);
+                let done = !matches!(frame, Ok(Some(_)));
+                if tx.send(frame).is_err() || done {
+                    break;
+                }
+            }
+        });
+
+        Ok(PythonWorker {
+            child,
+            stdin,
+            frames,
+            defined: HashMap::new(),
+        })
+    }
+
+    /// Check whether the worker process is still running
+    pub fn is_alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    /// Run a setup block in the worker's shared namespace. Blocks are
+    /// redefined afterwards so they see it as their globals.
+    pub fn setup(
+        &mut self,
+        code: &str,
+        origin: &SourceLocation,
+        timeout: Option<Duration>,
+        callbacks: &mut dyn Callbacks,
+    ) -> io::Result<Result<(), PythonException>> {
+        let response = self.request(&serde_json::json!({
+            "op": "setup",
+            "code": code,
+            "file": origin.file,
+            "line": origin.line,
+        }), timeout, callbacks)?;
+
+        self.defined.clear();
+        Ok(Self::into_result(response).map(|_| ()))
+    }
+
+    /// Make sure the worker has the current source for `name` compiled
+    pub fn ensure_defined(
+        &mut self,
+        name: &str,
+        params: &[String],
+        code: &str,
+        origin: &SourceLocation,
+        timeout: Option<Duration>,
+    ) -> io::Result<Result<(), PythonException>> {
+        if self.defined.get(name).is_some_and(|(defined, at)| defined == code && at == origin) {
+            return Ok(Ok(()));
+        }
+
+        let response = self.request(&serde_json::json!({
+            "op": "define",
+            "name": name,
+            "params": params,
+            "code": code,
+            "file": origin.file,
+            "line": origin.line,
+        }), timeout, &mut NoCallbacks)?;
+
+        let outcome = Self::into_result(response).map(|_| ());
+        if outcome.is_ok() {
+            self.defined.insert(name.to_string(), (code.to_string(), origin.clone()));
+        }
+        Ok(outcome)
+    }
+
+    /// Call a previously defined block, returning its JSON result or the
+    /// Python exception. `hybrid.call` requests made meanwhile are served by
+    /// `callbacks`.
+    ///
+    /// If no response arrives within `timeout` (which includes the time
+    /// spent in callbacks) the worker is killed and an
+    /// `io::ErrorKind::TimedOut` error is returned.
+    pub fn call(
+        &mut self,
+        name: &str,
+        args: &[serde_json::Value],
+        timeout: Option<Duration>,
+        callbacks: &mut dyn Callbacks,
+    ) -> io::Result<Result<serde_json::Value, PythonException>> {
+        let response = self.request(&serde_json::json!({
+            "op": "call",
+            "name": name,
+            "args": args,
+        }), timeout, callbacks)?;
+        Ok(Self::into_result(response))
+    }
+
+    /// Start an `iter[T]` block (already defined), returning the worker's
+    /// id for its iterator
+    pub fn open_stream(
+        &mut self,
+        name: &str,
+        args: &[serde_json::Value],
+        timeout: Option<Duration>,
+        callbacks: &mut dyn Callbacks,
+    ) -> io::Result<Result<u64, PythonException>> {
+        let response = self.request(&serde_json::json!({
+            "op": "iter",
+            "name": name,
+            "args": args,
+        }), timeout, callbacks)?;
+        Ok(Self::into_result(response).map(|id| id.as_u64().unwrap_or_default()))
+    }
+
+    /// Advance an iterator, returning its next item or `None` once it is exhausted
+    pub fn next_item(
+        &mut self,
+        stream: u64,
+        timeout: Option<Duration>,
+        callbacks: &mut dyn Callbacks,
+    ) -> io::Result<Result<Option<serde_json::Value>, PythonException>> {
+        let response = self.request(&serde_json::json!({
+            "op": "next",
+            "stream": stream,
+        }), timeout, callbacks)?;
+        if response.get("done").and_then(|done| done.as_bool()) == Some(true) {
+            return Ok(Ok(None));
+        }
+        Ok(Self::into_result(response).map(Some))
+    }
+
+    /// Discard an iterator, closing it if it is a generator
+    pub fn close_stream(&mut self, stream: u64, timeout: Option<Duration>) -> io::Result<()> {
+        self.request(&serde_json::json!({
+            "op": "close",
+            "stream": stream,
+        }), timeout, &mut NoCallbacks)?;
+        Ok(())
+    }
+
+    /// Send a request and wait for its response, answering any callback
+    /// frames the worker sends in between
+    fn request(
+        &mut self,
+        request: &serde_json::Value,
+        timeout: Option<Duration>,
+        callbacks: &mut dyn Callbacks,
+    ) -> io::Result<serde_json::Value> {
+        let deadline = timeout.map(|limit| Instant::now() + limit);
+        self.send(request)?;
+
+        loop {
+            let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            let frame = match process::wait_for(&self.frames, remaining) {
+                Ok(frame) => frame?,
+                Err(RecvTimeoutError::Timeout) => {
+                    process::kill(&mut self.child);
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "Python worker timed out"));
+                }
+                // The reader already passed on the end of stdout
+                Err(RecvTimeoutError::Disconnected) => None,
+            };
+            let frame = frame.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "Python worker exited unexpectedly")
+            })?;
+
+            let mut message: serde_json::Value =
+                serde_json::from_str(&frame).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let Some(name) = message.get("callback").and_then(|n| n.as_str()).map(str::to_string) else {
+                return Ok(message);
+            };
+
+            let args = match message.get_mut("args").map(serde_json::Value::take) {
+                Some(serde_json::Value::Array(args)) => args,
+                _ => Vec::new(),
+            };
+            let reply = match callbacks.call(&name, args) {
+                Ok(result) => serde_json::json!({ "ok": true, "result": result }),
+                Err(error) => serde_json::json!({ "ok": false, "error": error }),
+            };
+            self.send(&reply)?;
+        }
+    }
+
+    fn send(&mut self, message: &serde_json::Value) -> io::Result<()> {
+        let stdin = self.stdin.as_mut().ok_or_else(|| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        writeln!(stdin, "{}", message)?;
+        stdin.flush()
+    }
+
+    /// Exit status of a worker that has exited (or is about to, e.g. after
+    /// its stdout closed), or `None` if it is still running after a short
+    /// grace period
+    pub fn exit_status(&mut self) -> Option<std::process::ExitStatus> {
+        let deadline = Instant::now() + SHUTDOWN_GRACE;
+        loop {
+            match self.child.try_wait() {
+                Ok(Some(status)) => return Some(status),
+                Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+                _ => return None,
+            }
+        }
+    }
+
+    /// Kill the worker; later requests fail and `is_alive` is false
+    pub fn kill(&mut self) {
+        process::kill(&mut self.child);
+    }
+
+    fn into_result(response: serde_json::Value) -> Result<serde_json::Value, PythonException> {
+        match response {
+            serde_json::Value::Object(mut fields) => {
+                if fields.get("ok").and_then(|ok| ok.as_bool()) == Some(true) {
+                    return Ok(fields.remove("result").unwrap_or(serde_json::Value::Null));
+                }
+                let mut field = |name: &str| fields.remove(name).and_then(|v| v.as_str().map(str::to_string));
+                Err(PythonException {
+                    traceback: field("error").unwrap_or_else(|| "Unknown Python worker error".to_string()),
+                    exception_type: field("type").unwrap_or_else(|| "Exception".to_string()),
+                    message: field("message").unwrap_or_default(),
+                })
+            }
+            other => Err(PythonException {
+                exception_type: "HybridError".to_string(),
+                message: format!("Malformed worker response: {}", other),
+                traceback: String::new(),
+            }),
+        }
+    }
+}
+
+impl Drop for PythonWorker {
+    fn drop(&mut self) {
+        // Closing stdin ends the worker's request loop
+        drop(self.stdin.take());
+
+        let deadline = Instant::now() + SHUTDOWN_GRACE;
+        while Instant::now() < deadline {
+            match self.child.try_wait() {
+                Ok(Some(_)) | Err(_) => return,
+                Ok(None) => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Default for PythonRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,865 @@
+// runtime/rust.rs
+// #rust blocks: wrapper generation, compilation and execution
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::ast::HybridType;
+
+use super::cache;
+use super::channel;
+use super::foreign::{Callbacks, ForeignRuntime, StreamId, Validation};
+use super::info::{self, Capabilities, RuntimeInfo};
+use super::manager::{ForeignBlock, RuntimeError, RuntimeErrorKind};
+use super::process::{self, FramedChild, Outcome, ResourceLimits};
+use super::source_map;
+use super::workspace;
+
+/// Support code compiled into every wrapper: a minimal JSON reader and
+/// writer so arguments and results can be marshalled without external crates.
+const PRELUDE: &str = r#"
+#[allow(dead_code)]
+mod __hybrid {
+    use std::collections::HashMap;
+
+    pub enum Json {
+        Null,
+        Bool(bool),
+        Number(String),
+        String(String),
+        Array(Vec<Json>),
+        Object(Vec<(String, Json)>),
+    }
+
+    pub fn parse(text: &str) -> Json {
+        let mut parser = Parser { chars: text.chars().collect(), pos: 0 };
+        let value = parser.value();
+        parser.skip_ws();
+        if parser.pos != parser.chars.len() {
+            panic!("hybrid: trailing characters in argument JSON");
+        }
+        value
+    }
+
+    /// The call's arguments: a JSON array on the first line of stdin
+    pub fn read_args() -> Vec<Json> {
+        let mut line = String::new();
+        if let Err(e) = std::io::stdin().read_line(&mut line) {
+            panic!("hybrid: failed to read arguments: {}", e);
+        }
+        match parse(&line) {
+            Json::Array(items) => items,
+            _ => panic!("hybrid: expected an array of arguments"),
+        }
+    }
+
+    struct Parser {
+        chars: Vec<char>,
+        pos: usize,
+    }
+
+    impl Parser {
+        fn peek(&self) -> Option<char> {
+            self.chars.get(self.pos).copied()
+        }
+
+        fn next(&mut self) -> char {
+            let c = self.peek().unwrap_or_else(|| panic!("hybrid: unexpected end of argument JSON"));
+            self.pos += 1;
+            c
+        }
+
+        fn skip_ws(&mut self) {
+            while self.peek().map_or(false, |c| c.is_whitespace()) {
+                self.pos += 1;
+            }
+        }
+
+        fn expect(&mut self, word: &str) {
+            for expected in word.chars() {
+                if self.next() != expected {
+                    panic!("hybrid: invalid literal in argument JSON");
+                }
+            }
+        }
+
+        fn value(&mut self) -> Json {
+            self.skip_ws();
+            match self.peek() {
+                Some('n') => { self.expect("null"); Json::Null }
+                Some('t') => { self.expect("true"); Json::Bool(true) }
+                Some('f') => { self.expect("false"); Json::Bool(false) }
+                Some('"') => Json::String(self.string()),
+                Some('[') => {
+                    self.pos += 1;
+                    let mut items = Vec::new();
+                    self.skip_ws();
+                    if self.peek() == Some(']') {
+                        self.pos += 1;
+                        return Json::Array(items);
+                    }
+                    loop {
+                        items.push(self.value());
+                        self.skip_ws();
+                        match self.next() {
+                            ',' => continue,
+                            ']' => return Json::Array(items),
+                            c => panic!("hybrid: expected ',' or ']' in argument JSON, found {:?}", c),
+                        }
+                    }
+                }
+                Some('{') => {
+                    self.pos += 1;
+                    let mut fields = Vec::new();
+                    self.skip_ws();
+                    if self.peek() == Some('}') {
+                        self.pos += 1;
+                        return Json::Object(fields);
+                    }
+                    loop {
+                        self.skip_ws();
+                        let key = self.string();
+                        self.skip_ws();
+                        if self.next() != ':' {
+                            panic!("hybrid: expected ':' in argument JSON");
+                        }
+                        fields.push((key, self.value()));
+                        self.skip_ws();
+                        match self.next() {
+                            ',' => continue,
+                            '}' => return Json::Object(fields),
+                            c => panic!("hybrid: expected ',' or '}}' in argument JSON, found {:?}", c),
+                        }
+                    }
+                }
+                _ => {
+                    let start = self.pos;
+                    while self.peek().map_or(false, |c| c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E' || c.is_ascii_digit()) {
+                        self.pos += 1;
+                    }
+                    if start == self.pos {
+                        panic!("hybrid: invalid argument JSON");
+                    }
+                    Json::Number(self.chars[start..self.pos].iter().collect())
+                }
+            }
+        }
+
+        fn string(&mut self) -> String {
+            if self.next() != '"' {
+                panic!("hybrid: expected string in argument JSON");
+            }
+            let mut out = String::new();
+            loop {
+                match self.next() {
+                    '"' => return out,
+                    '\\' => match self.next() {
+                        'n' => out.push('\n'),
+                        't' => out.push('\t'),
+                        'r' => out.push('\r'),
+                        'b' => out.push('\u{8}'),
+                        'f' => out.push('\u{c}'),
+                        'u' => {
+                            let mut code = self.hex4();
+                            if (0xD800..0xDC00).contains(&code) {
+                                self.expect("\\u");
+                                code = 0x10000 + ((code - 0xD800) << 10) + (self.hex4() - 0xDC00);
+                            }
+                            out.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                        }
+                        c => out.push(c),
+                    },
+                    c => out.push(c),
+                }
+            }
+        }
+
+        fn hex4(&mut self) -> u32 {
+            let digits: String = (0..4).map(|_| self.next()).collect();
+            u32::from_str_radix(&digits, 16).unwrap_or_else(|_| panic!("hybrid: invalid \\u escape in argument JSON"))
+        }
+    }
+
+    pub fn as_i64(v: &Json, what: &str) -> i64 {
+        match v {
+            Json::Number(n) => match (n.parse::<i64>(), n.parse::<f64>()) {
+                (Ok(i), _) => i,
+                // Hybrid numbers are floats, so `3.0` is still an int
+                (_, Ok(f)) if f.fract() == 0.0 => f as i64,
+                _ => panic!("hybrid: {} is not an int", what),
+            },
+            _ => panic!("hybrid: expected int for {}", what),
+        }
+    }
+
+    pub fn as_f64(v: &Json, what: &str) -> f64 {
+        match v {
+            Json::Number(n) => n.parse::<f64>().unwrap_or_else(|_| panic!("hybrid: {} is not a float", what)),
+            _ => panic!("hybrid: expected float for {}", what),
+        }
+    }
+
+    pub fn as_bool(v: &Json, what: &str) -> bool {
+        match v {
+            Json::Bool(b) => *b,
+            _ => panic!("hybrid: expected bool for {}", what),
+        }
+    }
+
+    pub fn as_string(v: &Json, what: &str) -> String {
+        match v {
+            Json::String(s) => s.clone(),
+            _ => panic!("hybrid: expected string for {}", what),
+        }
+    }
+
+    /// `bytes` arrive as `{"$bytes": "<base64>"}`
+    pub fn as_bytes(v: &Json, what: &str) -> Vec<u8> {
+        let encoded = match v {
+            Json::Object(fields) => match fields.as_slice() {
+                [(key, Json::String(encoded))] if key == "$bytes" => encoded,
+                _ => panic!("hybrid: expected bytes for {}", what),
+            },
+            _ => panic!("hybrid: expected bytes for {}", what),
+        };
+        let mut out = Vec::with_capacity(encoded.len() / 4 * 3);
+        let (mut buffer, mut bits) = (0u32, 0);
+        for c in encoded.bytes().filter(|&c| c != b'=') {
+            let digit = match c {
+                b'A'..=b'Z' => c - b'A',
+                b'a'..=b'z' => c - b'a' + 26,
+                b'0'..=b'9' => c - b'0' + 52,
+                b'+' => 62,
+                b'/' => 63,
+                _ => panic!("hybrid: invalid base64 in {}", what),
+            };
+            buffer = (buffer << 6 | digit as u32) & 0xffff;
+            bits += 6;
+            if bits >= 8 {
+                bits -= 8;
+                out.push((buffer >> bits) as u8);
+            }
+        }
+        out
+    }
+
+    pub fn as_array<'a>(v: &'a Json, what: &str) -> &'a [Json] {
+        match v {
+            Json::Array(items) => items,
+            _ => panic!("hybrid: expected array for {}", what),
+        }
+    }
+
+    pub fn as_object<'a>(v: &'a Json, what: &str) -> &'a [(String, Json)] {
+        match v {
+            Json::Object(fields) => fields,
+            _ => panic!("hybrid: expected map for {}", what),
+        }
+    }
+
+    pub fn map_of<T>(fields: impl Iterator<Item = (String, T)>) -> HashMap<String, T> {
+        fields.collect()
+    }
+
+    pub fn float_json(f: f64) -> String {
+        if f.is_finite() { format!("{:?}", f) } else { "null".to_string() }
+    }
+
+    pub fn string_json(s: &str) -> String {
+        let mut out = String::with_capacity(s.len() + 2);
+        out.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+        out
+    }
+
+    pub fn bytes_json(bytes: &[u8]) -> String {
+        const DIGITS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut out = String::with_capacity(bytes.len() / 3 * 4 + 16);
+        out.push_str("{\"$bytes\":\"");
+        for chunk in bytes.chunks(3) {
+            let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    out.push(DIGITS[(n >> (18 - 6 * i)) as usize & 63] as char);
+                } else {
+                    out.push('=');
+                }
+            }
+        }
+        out.push_str("\"}");
+        out
+    }
+
+    pub fn array_json(items: impl Iterator<Item = String>) -> String {
+        format!("[{}]", items.collect::<Vec<_>>().join(","))
+    }
+
+    pub fn object_json<'a>(fields: impl Iterator<Item = (&'a String, String)>) -> String {
+        let fields: Vec<String> = fields.map(|(k, v)| format!("{}:{}", string_json(k), v)).collect();
+        format!("{{{}}}", fields.join(","))
+    }
+}
+"#;
+
+/// Runs `#rust` blocks as compiled binaries, one process per call
+#[derive(Default)]
+pub struct RustRuntime {
+    /// Block name -> (wrapper, binary) of the last build
+    prepared: HashMap<String, (Wrapper, PathBuf)>,
+    /// Running `iter[T]` blocks
+    streams: HashMap<StreamId, RustStream>,
+    next_stream: StreamId,
+}
+
+/// A running `iter[T]` block, printing one framed `{"item": ...}` line per item
+struct RustStream {
+    output: FramedChild,
+    block: ForeignBlock,
+    wrapper: Wrapper,
+    limits: ResourceLimits,
+}
+
+impl RustRuntime {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compile a block (or find it in the cache), with diagnostics pointing
+    /// at the `.hyb` file
+    fn build(block: &ForeignBlock, wrapper: &Wrapper) -> Result<PathBuf, RuntimeError> {
+        if block.dependencies.is_empty() {
+            Self::build_with_rustc(block, &wrapper.source).map_err(|e| wrapper.map_compile_error(e, block, "block.rs"))
+        } else {
+            Self::build_with_cargo(block, &wrapper.source).map_err(|e| wrapper.map_compile_error(e, block, "src/main.rs"))
+        }
+    }
+
+    /// Compile a std-only block with bare `rustc`
+    fn build_with_rustc(block: &ForeignBlock, wrapper: &str) -> Result<PathBuf, RuntimeError> {
+        let toolchain = rustc_version().unwrap_or_default();
+        let files = [("block.rs", wrapper)];
+        workspace::build_cached("rust", &block.name, &toolchain, &files, |dir| {
+            let output = dir.join(&format!("block{}", std::env::consts::EXE_SUFFIX));
+            let mut command = Command::new("rustc");
+            command.arg(dir.join("block.rs")).arg("-o").arg(&output);
+            (command, output)
+        })
+    }
+
+    /// Build a block with `@crate` dependencies as a generated Cargo
+    /// project. Builds run `--offline`, so the crates must already be in
+    /// the local registry cache or a vendored source configured for Cargo.
+    /// All such blocks share one target directory in the cache, so each
+    /// dependency is compiled only once.
+    fn build_with_cargo(block: &ForeignBlock, wrapper: &str) -> Result<PathBuf, RuntimeError> {
+        let io_error = |e: std::io::Error| RuntimeError::new("rust", format!("Failed to create cache directory: {}", e));
+        let target_dir = cache::language_dir("rust").map_err(io_error)?.join(CARGO_TARGET_DIR);
+
+        // A package name unique to the source keeps concurrent builds in the
+        // shared target directory from overwriting each other's binaries
+        let dependencies = cargo_dependencies(block);
+        let package = format!("hybrid_block_{}", cache::content_hash(&[wrapper, &dependencies]));
+        let manifest = format!(
+            "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\n{}",
+            package, dependencies
+        );
+
+        let toolchain = format!("cargo {}", rustc_version().unwrap_or_default());
+        let files = [("Cargo.toml", manifest.as_str()), ("src/main.rs", wrapper)];
+        workspace::build_cached("rust", &block.name, &toolchain, &files, |dir| {
+            let mut command = Command::new("cargo");
+            command
+                .arg("build")
+                .arg("--release")
+                .arg("--offline")
+                .arg("--quiet")
+                .arg("--manifest-path")
+                .arg(dir.join("Cargo.toml"))
+                .env("CARGO_TARGET_DIR", &target_dir);
+            let artifact = target_dir
+                .join("release")
+                .join(format!("{}{}", package, std::env::consts::EXE_SUFFIX));
+            (command, artifact)
+        })
+    }
+}
+
+/// Name of the shared Cargo target directory inside the Rust cache
+const CARGO_TARGET_DIR: &str = "cargo-target";
+
+/// `[dependencies]` lines for a block's `@crate` attributes
+fn cargo_dependencies(block: &ForeignBlock) -> String {
+    block
+        .dependencies
+        .iter()
+        .map(|dep| {
+            let features = dep
+                .features
+                .iter()
+                .map(|f| format!("{:?}", f))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("{} = {{ version = {:?}, features = [{}] }}\n", dep.name, dep.version, features)
+        })
+        .collect()
+}
+
+impl ForeignRuntime for RustRuntime {
+    fn language(&self) -> &str {
+        "rust"
+    }
+
+    fn info(&self) -> RuntimeInfo {
+        let capabilities = Capabilities {
+            streaming: true,
+            validation: true,
+            parallel: true,
+            ..Capabilities::default()
+        };
+        RuntimeInfo::probe("rust", "rustc", capabilities)
+    }
+
+    fn prepare(&mut self, block: &ForeignBlock) -> Result<(), RuntimeError> {
+        let wrapper = generate_wrapper(block).map_err(|message| RuntimeError::compile("rust", message))?;
+        if self.prepared.get(&block.name).is_some_and(|(built, _)| built.source == wrapper.source) {
+            return Ok(());
+        }
+
+        let binary_path = Self::build(block, &wrapper)?;
+        self.prepared.insert(block.name.clone(), (wrapper, binary_path));
+        Ok(())
+    }
+
+    fn fork(&self) -> Option<Box<dyn ForeignRuntime>> {
+        Some(Box::new(RustRuntime {
+            prepared: self.prepared.clone(),
+            ..Default::default()
+        }))
+    }
+
+    /// Compile the block ahead of time; the binary lands in the cache, so
+    /// `prepare` finds it there later
+    fn validator(&self, block: &ForeignBlock) -> Option<Validation> {
+        let block = block.clone();
+        Some(Box::new(move || {
+            let wrapper = generate_wrapper(&block).map_err(|message| RuntimeError::compile("rust", message))?;
+            Self::build(&block, &wrapper).map(|_| ())
+        }))
+    }
+
+    fn invoke(
+        &mut self,
+        block: &ForeignBlock,
+        args: &[serde_json::Value],
+        limits: &ResourceLimits,
+        _callbacks: &mut dyn Callbacks,
+    ) -> Result<serde_json::Value, RuntimeError> {
+        let (wrapper, binary_path) = self
+            .prepared
+            .get(&block.name)
+            .ok_or_else(|| RuntimeError::new("rust", format!("Block '{}' has not been compiled", block.name)))?;
+
+        // Arguments go on stdin as a JSON array, since argv has a size limit
+        let command = Command::new(binary_path);
+        let outcome = process::run_framed(command, arguments_line(args), limits)
+            .map_err(|e| RuntimeError::new("rust", format!("Failed to run binary: {}", e)))?;
+
+        match outcome {
+            Outcome::TimedOut => Err(RuntimeError::timeout("rust", &block.name, limits.timeout.unwrap_or_default())),
+            Outcome::Exited { status, stderr, .. } if limits.explains(&status, &stderr) => {
+                Err(RuntimeError::resource_limit("rust", &block.name, limits))
+            }
+            Outcome::Exited { status, frame, .. } if !status.success() => match frame.as_deref().and_then(Panic::parse) {
+                Some(panic) => Err(wrapper.map_panic(panic, block)),
+                // stderr is forwarded as it arrives, so whatever the block printed has already been shown
+                None => Err(RuntimeError::non_zero_exit("rust", &block.name, status, "")),
+            },
+            Outcome::Exited { frame: Some(json), .. } => serde_json::from_str(&json)
+                .map_err(|e| RuntimeError::decode("rust", format!("Failed to parse result: {}", e))),
+            Outcome::Exited { frame: None, .. } => {
+                Err(RuntimeError::new("rust", "Block exited without producing a result"))
+            }
+        }
+    }
+
+    fn open_stream(
+        &mut self,
+        block: &ForeignBlock,
+        args: &[serde_json::Value],
+        limits: &ResourceLimits,
+        _callbacks: &mut dyn Callbacks,
+    ) -> Result<StreamId, RuntimeError> {
+        let (wrapper, binary_path) = self
+            .prepared
+            .get(&block.name)
+            .ok_or_else(|| RuntimeError::new("rust", format!("Block '{}' has not been compiled", block.name)))?;
+
+        let command = Command::new(binary_path);
+        let output = FramedChild::spawn(command, arguments_line(args), limits)
+            .map_err(|e| RuntimeError::new("rust", format!("Failed to run binary: {}", e)))?;
+
+        self.next_stream += 1;
+        let stream = RustStream {
+            output,
+            block: block.clone(),
+            wrapper: wrapper.clone(),
+            limits: *limits,
+        };
+        self.streams.insert(self.next_stream, stream);
+        Ok(self.next_stream)
+    }
+
+    fn next_item(&mut self, stream: StreamId, _callbacks: &mut dyn Callbacks) -> Result<Option<serde_json::Value>, RuntimeError> {
+        let RustStream { output, block, wrapper, limits } = self
+            .streams
+            .get_mut(&stream)
+            .ok_or_else(|| RuntimeError::new("rust", "Stream is not open"))?;
+
+        let frame = match output.next_frame(limits.timeout) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                let status = output
+                    .wait()
+                    .map_err(|e| RuntimeError::new("rust", format!("Failed to wait for binary: {}", e)))?;
+                return match status {
+                    status if limits.explains(&status, &output.stderr()) => Err(RuntimeError::resource_limit("rust", &block.name, limits)),
+                    status if !status.success() => Err(RuntimeError::non_zero_exit("rust", &block.name, status, "")),
+                    _ => Ok(None),
+                };
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                return Err(RuntimeError::timeout("rust", &block.name, limits.timeout.unwrap_or_default()));
+            }
+            Err(e) => return Err(RuntimeError::new("rust", format!("Failed to read from binary: {}", e))),
+        };
+
+        if let Some(panic) = Panic::parse(&frame) {
+            let _ = output.wait();
+            return Err(wrapper.map_panic(panic, block));
+        }
+        match serde_json::from_str::<serde_json::Value>(&frame) {
+            Ok(serde_json::Value::Object(mut fields)) if fields.contains_key("item") => Ok(fields.remove("item")),
+            Ok(other) => Err(RuntimeError::decode("rust", format!("Unexpected stream frame: {}", other))),
+            Err(e) => Err(RuntimeError::decode("rust", format!("Failed to parse item: {}", e))),
+        }
+    }
+
+    fn close_stream(&mut self, stream: StreamId) {
+        // Dropping the child kills it if it is still producing items
+        self.streams.remove(&stream);
+    }
+
+    fn shutdown(&mut self) {
+        self.streams.clear();
+    }
+}
+
+/// Columns the wrapper indents a block's body by
+const BODY_INDENT: &str = "        ";
+
+/// Generated source for a block, and where the block's body sits in it
+#[derive(Clone)]
+pub struct Wrapper {
+    pub source: String,
+    /// 1-based wrapper line holding the body's first line
+    pub body_start: usize,
+}
+
+impl Wrapper {
+    /// Point a compile error's diagnostics at the `.hyb` file instead of
+    /// the wrapper, which is compiled as `wrapper_file`
+    fn map_compile_error(&self, error: RuntimeError, block: &ForeignBlock, wrapper_file: &str) -> RuntimeError {
+        let RuntimeErrorKind::Compile { diagnostics } = &*error.kind else {
+            return error;
+        };
+        let diagnostics = source_map::rewrite_wrapper_refs(
+            diagnostics,
+            wrapper_file,
+            self.body_start,
+            block.code.lines().count(),
+            BODY_INDENT.len(),
+            &block.origin,
+        );
+        let location = source_map::first_location(&diagnostics, &block.origin.file);
+        RuntimeError::compile("rust", diagnostics).with_location(location)
+    }
+
+    /// The error for a panic reported by the wrapper's panic hook
+    fn map_panic(&self, panic: Panic, block: &ForeignBlock) -> RuntimeError {
+        let body_len = block.code.lines().count();
+        let location = (panic.line >= self.body_start && panic.line < self.body_start + body_len).then(|| {
+            let column = panic.column.saturating_sub(BODY_INDENT.len()).max(1);
+            block.origin.body_line(panic.line - self.body_start + 1, Some(column))
+        });
+        RuntimeError {
+            message: format!("Block '{}' panicked: {}", block.name, panic.message),
+            ..RuntimeError::exception("rust", "panic", &panic.message, "")
+        }
+        .with_location(location)
+    }
+}
+
+/// The frame the wrapper's panic hook prints; `line` is a wrapper line, or
+/// 0 when the panic came from outside the wrapper (e.g. a dependency)
+struct Panic {
+    message: String,
+    line: usize,
+    column: usize,
+}
+
+impl Panic {
+    fn parse(frame: &str) -> Option<Panic> {
+        let json: serde_json::Value = serde_json::from_str(frame).ok()?;
+        Some(Panic {
+            message: json.get("panic")?.as_str()?.to_string(),
+            line: json.get("line")?.as_u64()? as usize,
+            column: json.get("column")?.as_u64()? as usize,
+        })
+    }
+}
+
+/// The stdin line carrying a call's arguments to the wrapper
+fn arguments_line(args: &[serde_json::Value]) -> String {
+    format!("{}\n", serde_json::Value::from(args.to_vec()))
+}
+
+/// `rustc --version` output, queried once per process
+pub fn rustc_version() -> Option<String> {
+    info::version(Path::new("rustc"))
+}
+
+/// Generate the full Rust source for a block: a `main` that decodes the
+/// JSON arguments (an array on the first line of stdin) into typed locals, runs the body and
+/// prints the result as a framed JSON line according to the declared
+/// return types. A panic hook reports panics as a framed
+/// `{"panic", "line", "column"}` object instead, with the wrapper position
+/// left for the runtime to map, so moving a block in its `.hyb` file does
+/// not change the source or invalidate the compile cache.
+pub fn generate_wrapper(block: &ForeignBlock) -> Result<Wrapper, String> {
+    let mut param_setup = Vec::new();
+    for (i, param) in block.parameters.iter().enumerate() {
+        let rust_type = rust_type(&param.param_type)?;
+        let decode = decode_expr(&param.param_type, "__json", &format!("parameter '{}'", param.name), 0)?;
+        param_setup.push(format!(
+            "    #[allow(unused_mut)]\n    let mut {name}: {ty} = {{ let __json = &__args[{i}]; {decode} }};",
+            name = param.name,
+            ty = rust_type,
+            i = i,
+            decode = decode,
+        ));
+    }
+
+    let marker = channel::RESULT_MARKER.escape_default();
+    // An iter[T] body may evaluate to anything iterable; each item is
+    // printed as soon as it is produced
+    let (result, emit) = match block.item_type() {
+        Some(item) => (
+            "let result = (|| {".to_string(),
+            format!(
+                r#"for __value in result {{
+        let __value: {ty} = __value;
+        println!("{marker}{{{{\"item\":{{}}}}}}", {encode});
+    }}"#,
+                ty = rust_type(item)?,
+                marker = marker,
+                encode = encode_expr(item, "&__value", 0)?,
+            ),
+        ),
+        None => {
+            let (return_type, encode) = return_encoding(&block.return_types)?;
+            (
+                format!("let result: {rt} = (|| -> {rt} {{", rt = return_type),
+                format!(r#"println!("{marker}{{}}", {encode});"#, marker = marker, encode = encode),
+            )
+        }
+    };
+
+    let head = format!(r#"#![allow(unused)]
+use std::collections::HashMap;
+use std::env;
+
+fn main() {{
+    std::panic::set_hook(Box::new(|info| {{
+        let payload = info.payload();
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "Box<dyn Any>".to_string());
+        let (line, column) = info
+            .location()
+            .filter(|l| l.file() == file!())
+            .map_or((0, 0), |l| (l.line(), l.column()));
+        println!(
+            "{marker}{{{{\"panic\":{{}},\"line\":{{}},\"column\":{{}}}}}}",
+            __hybrid::string_json(&message),
+            line,
+            column
+        );
+    }}));
+
+    let __args = __hybrid::read_args();
+    if __args.len() != {count} {{
+        panic!("hybrid: block '{name}' expects {count} arguments, got {{}}", __args.len());
+    }}
+{param_setup}
+
+    // User's function body
+    {result}
+"#,
+        marker = marker,
+        count = block.parameters.len(),
+        name = block.name,
+        param_setup = param_setup.join("\n"),
+        result = result,
+    );
+    let tail = format!(r#"
+    }})();
+
+    {emit}
+}}
+{prelude}"#,
+        emit = emit,
+        prelude = PRELUDE,
+    );
+
+    Ok(Wrapper {
+        body_start: head.lines().count() + 1,
+        source: format!("{}{}{}", head, indent_code(&block.code, BODY_INDENT), tail),
+    })
+}
+
+/// The Rust type of a block's result and the expression encoding `result`
+/// as JSON text
+fn return_encoding(return_types: &[HybridType]) -> Result<(String, String), String> {
+    Ok(match return_types {
+        [] => ("()".to_string(), "\"null\".to_string()".to_string()),
+        [single] => (rust_type(single)?, encode_expr(single, "&result", 0)?),
+        many => {
+            let mut types = Vec::new();
+            let mut parts = Vec::new();
+            for (i, ty) in many.iter().enumerate() {
+                types.push(rust_type(ty)?);
+                parts.push(encode_expr(ty, &format!("&result.{}", i), 0)?);
+            }
+            (
+                format!("({})", types.join(", ")),
+                format!("__hybrid::array_json(vec![{}].into_iter())", parts.join(", ")),
+            )
+        }
+    })
+}
+
+/// Error for `iter[T]` anywhere but as a block's only return type
+const ITER_POSITION: &str = "iter[...] is only supported as a block's only return type";
+
+/// The Rust type a Hybrid type is decoded into
+fn rust_type(ty: &HybridType) -> Result<String, String> {
+    Ok(match ty {
+        HybridType::Int => "i64".to_string(),
+        HybridType::Float => "f64".to_string(),
+        HybridType::String => "String".to_string(),
+        HybridType::Bool => "bool".to_string(),
+        HybridType::Bytes => "Vec<u8>".to_string(),
+        HybridType::Void | HybridType::Null => "()".to_string(),
+        HybridType::Iter(_) => return Err(ITER_POSITION.to_string()),
+        HybridType::Array(inner) => format!("Vec<{}>", rust_type(inner)?),
+        HybridType::Map(key, value) => {
+            if **key != HybridType::String {
+                return Err(format!("Map keys must be strings in #rust blocks, found {:?}", key));
+            }
+            format!("HashMap<String, {}>", rust_type(value)?)
+        }
+    })
+}
+
+/// Expression decoding the `__hybrid::Json` named `var` into `ty`
+fn decode_expr(ty: &HybridType, var: &str, what: &str, depth: usize) -> Result<String, String> {
+    let what_lit = format!("{:?}", what);
+    Ok(match ty {
+        HybridType::Int => format!("__hybrid::as_i64(&{}, {})", var, what_lit),
+        HybridType::Float => format!("__hybrid::as_f64(&{}, {})", var, what_lit),
+        HybridType::String => format!("__hybrid::as_string(&{}, {})", var, what_lit),
+        HybridType::Bool => format!("__hybrid::as_bool(&{}, {})", var, what_lit),
+        HybridType::Bytes => format!("__hybrid::as_bytes(&{}, {})", var, what_lit),
+        HybridType::Void | HybridType::Null => "()".to_string(),
+        HybridType::Iter(_) => return Err(ITER_POSITION.to_string()),
+        HybridType::Array(inner) => {
+            let item = format!("__item{}", depth);
+            format!(
+                "__hybrid::as_array(&{var}, {what}).iter().map(|{item}| {decode}).collect::<Vec<_>>()",
+                var = var,
+                what = what_lit,
+                item = item,
+                decode = decode_expr(inner, &format!("*{}", item), what, depth + 1)?,
+            )
+        }
+        HybridType::Map(_, value) => {
+            let key = format!("__key{}", depth);
+            let item = format!("__item{}", depth);
+            format!(
+                "__hybrid::map_of(__hybrid::as_object(&{var}, {what}).iter().map(|({key}, {item})| ({key}.clone(), {decode})))",
+                var = var,
+                what = what_lit,
+                key = key,
+                item = item,
+                decode = decode_expr(value, &format!("*{}", item), what, depth + 1)?,
+            )
+        }
+    })
+}
+
+/// Expression encoding the value behind the reference expression `var`
+/// (of type `&ty`) as JSON text
+fn encode_expr(ty: &HybridType, var: &str, depth: usize) -> Result<String, String> {
+    Ok(match ty {
+        HybridType::Int | HybridType::Bool => format!("({}).to_string()", var),
+        HybridType::Float => format!("__hybrid::float_json(*({}))", var),
+        HybridType::String => format!("__hybrid::string_json({})", var),
+        HybridType::Bytes => format!("__hybrid::bytes_json({})", var),
+        HybridType::Void | HybridType::Null => "\"null\".to_string()".to_string(),
+        HybridType::Iter(_) => return Err(ITER_POSITION.to_string()),
+        HybridType::Array(inner) => {
+            let item = format!("__item{}", depth);
+            format!(
+                "__hybrid::array_json(({var}).iter().map(|{item}| {encode}))",
+                var = var,
+                item = item,
+                encode = encode_expr(inner, &item, depth + 1)?,
+            )
+        }
+        HybridType::Map(key, value) => {
+            if **key != HybridType::String {
+                return Err(format!("Map keys must be strings in #rust blocks, found {:?}", key));
+            }
+            let key = format!("__key{}", depth);
+            let item = format!("__item{}", depth);
+            format!(
+                "__hybrid::object_json(({var}).iter().map(|({key}, {item})| ({key}, {encode})))",
+                var = var,
+                key = key,
+                item = item,
+                encode = encode_expr(value, &item, depth + 1)?,
+            )
+        }
+    })
+}
+
+fn indent_code(code: &str, indent: &str) -> String {
+    code.lines()
+        .map(|line| format!("{}{}", indent, line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
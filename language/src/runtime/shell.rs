@@ -0,0 +1,145 @@
+// runtime/shell.rs
+// #shell / #bash blocks executed by bash
+
+use std::process::Command;
+
+use crate::ast::HybridType;
+
+use super::foreign::{Callbacks, ForeignRuntime};
+use super::info::{Capabilities, RuntimeInfo};
+use super::manager::{ForeignBlock, RuntimeError, RuntimeManager};
+use super::process::{self, ResourceLimits};
+use super::source_map;
+
+/// Runs shell blocks with `bash -c`, one process per call.
+///
+/// Each parameter is exported as an environment variable of the same name
+/// (strings as-is, everything else as JSON) and also passed positionally as
+/// `$1`, `$2`, ...; the values never pass through the shell's parser, so
+/// `"$name"` is always safe to use. What the block prints is its result:
+/// the raw text for a `string` return type, JSON for any other type, and
+/// forwarded to Hybrid's stdout line by line, as it is printed, for blocks
+/// without a return type.
+#[derive(Default)]
+pub struct ShellRuntime;
+
+impl ShellRuntime {
+    pub fn new() -> Self {
+        ShellRuntime
+    }
+
+    /// Text form of an argument as seen by the script
+    fn arg_text(value: &serde_json::Value) -> String {
+        match value {
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        }
+    }
+
+    /// Point bash's `<name>: line N:` diagnostics at the `.hyb` file
+    fn map_stderr(block: &ForeignBlock, stderr: &str) -> String {
+        let prefix = format!("{}: line ", block.name);
+        stderr
+            .lines()
+            .map(|line| {
+                let Some(rest) = line.strip_prefix(&prefix) else {
+                    return line.to_string();
+                };
+                let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+                match digits.parse::<usize>() {
+                    Ok(n) if rest[digits.len()..].starts_with(':') => {
+                        format!("{}{}", block.origin.body_line(n, None), &rest[digits.len()..])
+                    }
+                    _ => line.to_string(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Whether the block's stdout is output rather than its result
+    fn is_void(block: &ForeignBlock) -> bool {
+        matches!(block.return_types.as_slice(), [] | [HybridType::Void])
+    }
+
+    /// Turn the block's stdout into its JSON result
+    fn decode_stdout(block: &ForeignBlock, stdout: &[u8]) -> Result<serde_json::Value, RuntimeError> {
+        let text = String::from_utf8_lossy(stdout);
+        match block.return_types.as_slice() {
+            // Already forwarded while the block ran
+            [] | [HybridType::Void] => Ok(serde_json::Value::Null),
+            [HybridType::String] => {
+                let text = text.strip_suffix('\n').unwrap_or(&text);
+                Ok(serde_json::Value::String(text.strip_suffix('\r').unwrap_or(text).to_string()))
+            }
+            [HybridType::Bytes] => Ok(RuntimeManager::bytes_to_json(stdout)),
+            _ => serde_json::from_str(text.trim()).map_err(|e| {
+                RuntimeError::decode(
+                    "shell",
+                    format!("Block '{}' must print JSON for its return type, got {:?}: {}", block.name, text.trim(), e),
+                )
+            }),
+        }
+    }
+}
+
+impl ForeignRuntime for ShellRuntime {
+    fn language(&self) -> &str {
+        "shell"
+    }
+
+    fn aliases(&self) -> &[&str] {
+        &["bash"]
+    }
+
+    fn info(&self) -> RuntimeInfo {
+        let capabilities = Capabilities {
+            parallel: true,
+            ..Capabilities::default()
+        };
+        RuntimeInfo::probe("shell", "bash", capabilities)
+    }
+
+    /// Every call already runs in its own `bash` process
+    fn fork(&self) -> Option<Box<dyn ForeignRuntime>> {
+        Some(Box::new(ShellRuntime))
+    }
+
+    fn invoke(
+        &mut self,
+        block: &ForeignBlock,
+        args: &[serde_json::Value],
+        limits: &ResourceLimits,
+        _callbacks: &mut dyn Callbacks,
+    ) -> Result<serde_json::Value, RuntimeError> {
+        let values: Vec<String> = args.iter().map(Self::arg_text).collect();
+
+        let mut command = Command::new("bash");
+        command.arg("-c").arg(&block.code).arg(&block.name).args(&values);
+        for (param, value) in block.parameters.iter().zip(&values) {
+            command.env(&param.name, value);
+        }
+
+        let run = if Self::is_void(block) { process::run_forwarding_stdout } else { process::run_captured };
+        let output = run(command, limits)
+            .map_err(|e| RuntimeError::new("shell", format!("Failed to run bash: {}", e)))?
+            .ok_or_else(|| RuntimeError::timeout("shell", &block.name, limits.timeout.unwrap_or_default()))?;
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if limits.explains(&output.status, &stderr) {
+            return Err(RuntimeError::resource_limit("shell", &block.name, limits));
+        }
+        let stderr = Self::map_stderr(block, &stderr);
+        if !output.status.success() {
+            let location = source_map::first_location(&stderr, &block.origin.file);
+            return Err(RuntimeError::non_zero_exit("shell", &block.name, output.status, &stderr).with_location(location));
+        }
+
+        // A successful block's diagnostics are still worth seeing
+        if !stderr.is_empty() {
+            eprintln!("{}", stderr);
+        }
+
+        Self::decode_stdout(block, &output.stdout)
+    }
+}
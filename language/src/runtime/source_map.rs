@@ -0,0 +1,204 @@
+// runtime/source_map.rs
+// Mapping foreign block diagnostics back to .hyb source lines
+
+use std::fmt;
+
+/// A position in a `.hyb` file (1-based line and column)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+    pub column: Option<usize>,
+}
+
+impl SourceLocation {
+    pub fn new(file: impl Into<String>, line: usize) -> Self {
+        SourceLocation {
+            file: file.into(),
+            line,
+            column: None,
+        }
+    }
+
+    /// Location of line `body_line` (1-based) of a block body that starts
+    /// at `self`
+    pub fn body_line(&self, body_line: usize, column: Option<usize>) -> SourceLocation {
+        SourceLocation {
+            file: self.file.clone(),
+            line: self.line + body_line.saturating_sub(1),
+            column,
+        }
+    }
+}
+
+impl Default for SourceLocation {
+    fn default() -> Self {
+        SourceLocation::new("<input>", 1)
+    }
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.column {
+            Some(column) => write!(f, "{}:{}:{}", self.file, self.line, column),
+            None => write!(f, "{}:{}", self.file, self.line),
+        }
+    }
+}
+
+/// The innermost `File "<file>", line N` frame of a Python traceback
+pub fn traceback_location(traceback: &str, file: &str) -> Option<SourceLocation> {
+    let prefix = format!("File \"{}\", line ", file);
+    traceback.lines().rev().find_map(|line| {
+        let rest = &line[line.find(&prefix)? + prefix.len()..];
+        let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+        Some(SourceLocation::new(file, digits.parse().ok()?))
+    })
+}
+
+/// The first `<file>:line[:column]` reference in compiler or stack output
+pub fn first_location(text: &str, file: &str) -> Option<SourceLocation> {
+    let prefix = format!("{}:", file);
+    let mut search = text;
+    while let Some(pos) = search.find(&prefix) {
+        let rest = &search[pos + prefix.len()..];
+        if let Some((line, column)) = parse_line_column(rest) {
+            return Some(SourceLocation { file: file.to_string(), line, column });
+        }
+        search = rest;
+    }
+    None
+}
+
+/// Rewrite `path:line:column` references to a generated wrapper (any path
+/// ending in `wrapper_file`) into `.hyb` locations, as found in rustc output
+/// (` --> /tmp/.../block.rs:12:9` and the numbered source gutter below it)
+/// and C compiler output (`/tmp/.../block.c:12:9: error: ...`).
+///
+/// `body_start` is the wrapper line holding the body's first line, `body_len`
+/// the number of body lines and `indent` the columns the wrapper indents the
+/// body by. References outside the body are left alone.
+pub fn rewrite_wrapper_refs(
+    text: &str,
+    wrapper_file: &str,
+    body_start: usize,
+    body_len: usize,
+    indent: usize,
+    origin: &SourceLocation,
+) -> String {
+    let to_body_line = |line: usize| (line >= body_start && line < body_start + body_len).then(|| line - body_start + 1);
+
+    let mut out = Vec::new();
+    // Gutter numbers are rewritten only inside a snippet that points into the body
+    let mut in_body_snippet = false;
+    for line in text.lines() {
+        if let Some(pos) = line.find(&format!("{}:", wrapper_file)) {
+            in_body_snippet = false;
+            let rest = &line[pos + wrapper_file.len() + 1..];
+            let location = parse_line_column(rest).and_then(|(line_no, column)| {
+                let column = column.map(|c| c.saturating_sub(indent).max(1));
+                Some(origin.body_line(to_body_line(line_no)?, column))
+            });
+            if let Some(rewritten) = location {
+                in_body_snippet = true;
+                // The reference is the whole whitespace-delimited path
+                let start = line[..pos].rfind(char::is_whitespace).map_or(0, |i| i + 1);
+                let end = rest.find(|c: char| !c.is_ascii_digit() && c != ':').unwrap_or(rest.len());
+                let separator = if rest[..end].ends_with(':') { ":" } else { "" };
+                out.push(format!("{}{}{}{}", &line[..start], rewritten, separator, &rest[end..]));
+                continue;
+            }
+        } else if in_body_snippet
+            && let Some(rewritten) = rewrite_snippet_line(line, indent, |n| to_body_line(n).map(|b| origin.line + b - 1))
+        {
+            out.push(rewritten);
+            continue;
+        }
+        out.push(line.to_string());
+    }
+    out.join("\n")
+}
+
+/// Rewrite a compiler snippet line (`151 |         return n +;` or the caret
+/// line under it): map the line number and undo the wrapper's indentation
+fn rewrite_snippet_line(line: &str, indent: usize, map: impl Fn(usize) -> Option<usize>) -> Option<String> {
+    let bar = line.find(" |")?;
+    let gutter = line[..bar].trim();
+    let number = if gutter.is_empty() {
+        String::new()
+    } else {
+        map(gutter.parse().ok()?)?.to_string()
+    };
+
+    let code = &line[bar + 2..];
+    let code = match code.strip_prefix(&format!(" {}", " ".repeat(indent))) {
+        Some(rest) => format!(" {}", rest),
+        None => code.to_string(),
+    };
+    Some(format!("{:>width$} |{}", number, code, width = bar))
+}
+
+fn parse_line_column(text: &str) -> Option<(usize, Option<usize>)> {
+    let digits: String = text.chars().take_while(char::is_ascii_digit).collect();
+    let line = digits.parse().ok()?;
+    let column = text[digits.len()..]
+        .strip_prefix(':')
+        .map(|rest| rest.chars().take_while(char::is_ascii_digit).collect::<String>())
+        .and_then(|c| c.parse().ok());
+    Some((line, column))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites_rustc_reference_and_gutter() {
+        let diagnostics = "\
+error: expected expression, found `;`
+  --> /tmp/hybrid-build-1-0-add/block.rs:12:19
+   |
+12 |         return n +;
+   |                   ^ expected expression";
+        let origin = SourceLocation::new("main.hyb", 5);
+        let rewritten = rewrite_wrapper_refs(diagnostics, "block.rs", 11, 3, 4, &origin);
+        assert_eq!(
+            rewritten,
+            "\
+error: expected expression, found `;`
+  --> main.hyb:6:15
+   |
+ 6 |     return n +;
+   |               ^ expected expression"
+        );
+    }
+
+    #[test]
+    fn rewrites_gcc_reference_and_keeps_refs_outside_the_body() {
+        let diagnostics = "\
+/tmp/build/block.c:3:1: note: in the prelude
+/tmp/build/block.c:21:5: error: 'y' undeclared";
+        let origin = SourceLocation::new("main.hyb", 40);
+        let rewritten = rewrite_wrapper_refs(diagnostics, "block.c", 20, 2, 4, &origin);
+        assert_eq!(
+            rewritten,
+            "\
+/tmp/build/block.c:3:1: note: in the prelude
+main.hyb:41:1: error: 'y' undeclared"
+        );
+    }
+
+    #[test]
+    fn finds_innermost_traceback_frame_and_first_reference() {
+        let traceback = "\
+Traceback (most recent call last):
+  File \"main.hyb\", line 7, in outer
+  File \"main.hyb\", line 12, in inner
+ValueError: bad";
+        assert_eq!(traceback_location(traceback, "main.hyb"), Some(SourceLocation::new("main.hyb", 12)));
+        assert_eq!(
+            first_location("at f (main.hyb:3:9)\nat g (main.hyb:8:1)", "main.hyb"),
+            Some(SourceLocation { file: "main.hyb".to_string(), line: 3, column: Some(9) })
+        );
+    }
+}
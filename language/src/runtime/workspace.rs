@@ -0,0 +1,138 @@
+// runtime/workspace.rs
+// Private scratch directories for compiling foreign blocks
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::cache;
+use super::manager::RuntimeError;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A uniquely named build directory under the system temp dir.
+///
+/// The name combines the process id, a per-process counter and the block
+/// name, so parallel `hybrid` processes (and different blocks in one
+/// process) never share files. The directory and everything in it is
+/// removed when the value is dropped, including on error paths.
+pub struct BuildDir {
+    path: PathBuf,
+}
+
+impl BuildDir {
+    /// Create a fresh build directory for `block_name`
+    pub fn new(block_name: &str) -> io::Result<Self> {
+        let label: String = block_name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+            .collect();
+
+        loop {
+            let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+            let path = std::env::temp_dir().join(format!(
+                "hybrid-build-{}-{}-{}",
+                std::process::id(),
+                id,
+                label
+            ));
+            // create_dir (not create_dir_all) fails if a stale directory with
+            // the same name is left over, in which case we try the next id
+            match fs::create_dir(&path) {
+                Ok(()) => return Ok(BuildDir { path }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path of a file inside the build directory
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+}
+
+impl Drop for BuildDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Move a finished artifact to `dest` atomically.
+///
+/// The artifact is first placed next to `dest` under a unique name and then
+/// renamed, so a concurrent reader either sees no file or the complete one.
+pub fn install_artifact(artifact: &Path, dest: &Path) -> io::Result<()> {
+    let staging = dest.with_extension(format!(
+        "partial-{}-{}",
+        std::process::id(),
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    // The build dir may live on a different filesystem than the cache
+    if fs::rename(artifact, &staging).is_err() {
+        fs::copy(artifact, &staging)?;
+    }
+
+    let result = fs::rename(&staging, dest);
+    if result.is_err() {
+        let _ = fs::remove_file(&staging);
+    }
+    result
+}
+
+/// Build an executable from `files` (relative path, contents), reusing the
+/// cached artifact when the same files were already built by the same
+/// `toolchain`.
+///
+/// The files are written into a fresh `BuildDir`, and `compile` returns the
+/// build command together with the path of the artifact it produces.
+/// A failed build returns the compiler's stderr as the error message.
+pub fn build_cached(
+    language: &str,
+    block_name: &str,
+    toolchain: &str,
+    files: &[(&str, &str)],
+    compile: impl FnOnce(&BuildDir) -> (Command, PathBuf),
+) -> Result<PathBuf, RuntimeError> {
+    let io_error = |what: &str, e: io::Error| RuntimeError::new(language, format!("{}: {}", what, e));
+
+    let mut parts = vec![toolchain];
+    for (name, contents) in files {
+        parts.push(name);
+        parts.push(contents);
+    }
+    let key = cache::content_hash(&parts);
+    let cache_dir = cache::language_dir(language).map_err(|e| io_error("Failed to create cache directory", e))?;
+    let binary_path = cache_dir.join(format!("{}{}", key, std::env::consts::EXE_SUFFIX));
+
+    if binary_path.exists() {
+        return Ok(binary_path);
+    }
+
+    // Compile in a private build directory, removed again on every path
+    let build_dir = BuildDir::new(block_name).map_err(|e| io_error("Failed to create build directory", e))?;
+    for (name, contents) in files {
+        let path = build_dir.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| io_error("Failed to create build directory", e))?;
+        }
+        fs::write(&path, contents).map_err(|e| io_error("Failed to write block source", e))?;
+    }
+
+    let (mut command, artifact) = compile(&build_dir);
+    let compile_output = command.output().map_err(|e| io_error("Failed to run compiler", e))?;
+
+    if !compile_output.status.success() {
+        return Err(RuntimeError::compile(language, String::from_utf8_lossy(&compile_output.stderr)));
+    }
+
+    install_artifact(&artifact, &binary_path).map_err(|e| io_error("Failed to store compiled block", e))?;
+    Ok(binary_path)
+}
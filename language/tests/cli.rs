@@ -0,0 +1,140 @@
+// tests/cli.rs
+// `hybrid run`: pre-flight validation, --no-validate and exit status
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Write `source` to a fresh `.hyb` file and run it with `hybrid run`
+fn run(name: &str, source: &str, extra: &[&str]) -> (PathBuf, Output) {
+    let path = std::env::temp_dir().join(format!("hybrid-cli-{}-{}.hyb", std::process::id(), name));
+    fs::write(&path, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_hybrid"))
+        .arg("run")
+        .args(extra)
+        .arg(&path)
+        .output()
+        .unwrap();
+    (path, output)
+}
+
+const BROKEN: &str = r#"speak("started");
+
+#python
+int block broken(int x) {
+    return (x
+}
+
+speak(broken(1));
+"#;
+
+#[test]
+fn validation_failure_is_reported_before_running() {
+    let (path, output) = run("validate", BROKEN, &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!stdout.contains("started"), "{}", stdout);
+    assert!(stderr.contains(&format!("Validation error: [python] {}:5", path.display())), "{}", stderr);
+    assert!(stderr.contains("1 foreign block failed validation"), "{}", stderr);
+}
+
+#[test]
+fn validation_finds_blocks_nested_in_control_flow() {
+    let source = r#"speak("started");
+
+if (1 > 0) {
+    #python
+    int block broken(int x) {
+        return (x
+    }
+    speak(broken(1));
+}
+"#;
+    let (path, output) = run("nested", source, &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!stdout.contains("started"), "{}", stdout);
+    assert!(stderr.contains(&format!("Validation error: [python] {}:6", path.display())), "{}", stderr);
+}
+
+#[test]
+fn no_validate_reports_the_error_on_first_call() {
+    let (path, output) = run("no-validate", BROKEN, &["--no-validate"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout.contains("started"), "{}", stdout);
+    assert!(!stderr.contains("Validation error"), "{}", stderr);
+    assert!(stderr.contains("Runtime error: [python]"), "{}", stderr);
+}
+
+#[test]
+fn validation_does_not_evaluate_attribute_expressions() {
+    let source = r#"@timeout(speak("evaluated") + 5)
+#python
+int block twice(int n) {
+    return n * 2
+}
+"#;
+    let (path, output) = run("attributes", source, &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(stdout.matches("evaluated").count(), 1, "{}", stdout);
+}
+
+#[test]
+fn invalid_crate_name_is_rejected() {
+    let source = r#"@crate("regex = \"1\"\n[patch]", "1")
+#rust
+int block one() {
+    1
+}
+
+speak(one());
+"#;
+    let (path, output) = run("crate", source, &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr.contains("invalid crate name"), "{}", stderr);
+}
+
+#[test]
+fn void_shell_output_is_forwarded_as_it_is_printed() {
+    let source = r#"@timeout(1)
+#shell
+void block slow() {
+    echo first
+    sleep 5
+    echo second
+}
+
+slow();
+"#;
+    let (path, output) = run("shell", source, &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    fs::remove_file(&path).unwrap();
+
+    // Printed before the block was killed, so not lost with it
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout.trim(), "first");
+}
+
+#[test]
+fn successful_run_exits_zero() {
+    let (path, output) = run("ok", "speak(1 + 1);\n", &[]);
+    fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "2");
+}